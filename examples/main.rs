@@ -4,11 +4,102 @@
 //! CS410P Rust Programming
 //! Spring 2021
 
-use tiny_http;
+use tiny_http::{Header, PostData, Response, Router, Server, StatusCode};
+
+// Greet whoever is named by the path, as in `/hello/greg`.
+fn hello(req: &Header) -> Response {
+    let name = req.get_param("name").unwrap_or("stranger");
+    let mut res = Response::from_status(StatusCode::OK);
+    res.set_body("text/plain; charset=utf-8", format!("Hello {}!\n", name));
+    res
+}
+
+// Answer the form of `http/demos/test.html` by listing what was posted.
+fn echo_post(req: &Header) -> Response {
+    let mut text = format!("POST {}\n", req.get_path());
+    match req.post_data() {
+        PostData::Empty => text.push_str("Nothing was posted\n"),
+        PostData::Form(fields) => {
+            for (name, value) in fields {
+                text.push_str(&format!("{} = {}\n", name, value));
+            }
+        }
+        PostData::Multipart(multipart) => {
+            for part in multipart.parts() {
+                match part.filename() {
+                    Some(file) => text.push_str(&format!(
+                        "{} = file {} ({} bytes)\n",
+                        part.name(),
+                        file,
+                        part.len()
+                    )),
+                    None => text.push_str(&format!(
+                        "{} = {}\n",
+                        part.name(),
+                        String::from_utf8_lossy(part.bytes().unwrap_or_default())
+                    )),
+                }
+            }
+        }
+        PostData::Raw(body) => text.push_str(&format!("{} bytes\n", body.len())),
+    }
+
+    let mut res = Response::from_status(StatusCode::OK);
+    res.set_body("text/plain; charset=utf-8", text);
+    res
+}
+
+// Block until asked to shut down with SIGINT or SIGTERM. SIGHUP is left
+// to the access log, which reopens its file on it.
+#[cfg(unix)]
+fn wait_for_signal() {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGINT, SIGTERM]).expect("Could not set the signal handler");
+    signals.forever().next();
+}
+
+// Block until asked to shut down with Ctrl-C.
+#[cfg(not(unix))]
+fn wait_for_signal() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    })
+    .expect("Could not set the signal handler");
+    let _ = rx.recv();
+}
 
 fn main() {
-    match tiny_http::tiny_http() {
+    // Anything not routed here is served from `doc_root`
+    let router = Router::new()
+        .get("/hello/:name", hello)
+        .post("/*", echo_post);
+
+    let server = match Server::builder()
+        .config_file("Config.toml")
+        .router(router)
+        .build()
+    {
+        Ok(server) => server,
+        Err(e) => panic!("An error occured in the server! {}", e.message),
+    };
+
+    let server = match server.start() {
+        Ok(server) => server,
+        Err(e) => panic!("An error occured in the server! {}", e.message),
+    };
+    println!("Listening on {}", server.local_addr());
+    if let Some(addr) = server.tls_addr() {
+        println!("Listening for HTTPS on {}", addr);
+    }
+
+    wait_for_signal();
+
+    println!("Shutting down");
+    match server.shutdown() {
         Ok(_) => (),
-        Err(_) => panic!("An error occured in the server!"),
+        Err(e) => panic!("An error occured in the server! {}", e.message),
     }
 }
@@ -0,0 +1,280 @@
+//! Access Log
+//!
+//! One line per response in the Common Log Format, or the Combined Log
+//! Format which adds the `Referer` and `User-Agent` of the request. Lines
+//! are buffered and flushed about once a second. On a SIGHUP the log file
+//! is reopened, so it can be rotated by moving it away. Once every log is
+//! closed SIGHUP ends the process again, if it did before the first was
+//! opened.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use log::error;
+
+use crate::configuration::{Config, DEFAULT_ACCESS_LOG_FORMAT};
+use crate::date;
+use crate::protocol::{RequestField, StatusCode};
+use crate::request::Header;
+use crate::TinyHttpError;
+
+/// Bytes of log lines buffered before they are written out.
+const LOG_BUFFER: usize = 8192;
+
+// What the process does on SIGHUP, shared by every access log in it.
+#[cfg(unix)]
+struct Hangup {
+    /// Access logs open
+    logs: usize,
+    /// Did SIGHUP end the process before the first log was opened?
+    was_default: Option<bool>,
+    /// The handler doing that again while no log is open
+    default: Option<signal_hook::SigId>,
+}
+
+#[cfg(unix)]
+static HANGUP: Mutex<Hangup> = Mutex::new(Hangup {
+    logs: 0,
+    was_default: None,
+    default: None,
+});
+
+/// Where and how responses are logged.
+pub struct AccessLog {
+    path: PathBuf,
+    combined: bool,
+    writer: Mutex<BufWriter<File>>,
+    /// Set by SIGHUP
+    reopen: Arc<AtomicBool>,
+    /// The SIGHUP handler, removed with the log
+    #[cfg(unix)]
+    signal: signal_hook::SigId,
+}
+
+/// What is logged about one response.
+pub struct Entry<'a> {
+    pub addr: Option<SocketAddr>,
+    /// `None` when the request could not be read at all
+    pub header: Option<&'a Header>,
+    pub status: StatusCode,
+    /// Bytes of body sent
+    pub bytes: u64,
+}
+
+impl AccessLog {
+    /// Open the log set by `access_log`, if there is one. The format is
+    /// picked by `access_log_format`, `common` or `combined`.
+    pub fn open(config: &Config) -> Result<Option<AccessLog>, TinyHttpError> {
+        let path = match &config.access_log {
+            Some(x) => PathBuf::from(x),
+            None => return Ok(None),
+        };
+
+        let format = match &config.access_log_format {
+            Some(x) => x.as_str(),
+            None => DEFAULT_ACCESS_LOG_FORMAT,
+        };
+        let combined = match format {
+            "combined" => true,
+            "common" => false,
+            x => {
+                return Err(TinyHttpError {
+                    message: format!("Unknown access_log_format {}", x),
+                })
+            }
+        };
+
+        let file = match open_file(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(TinyHttpError {
+                    message: format!("Could not open access log {}! {}", path.display(), e),
+                })
+            }
+        };
+
+        let reopen = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        let signal = match hangup_open(&reopen) {
+            Ok(signal) => signal,
+            Err(e) => {
+                return Err(TinyHttpError {
+                    message: format!("Could not listen for SIGHUP! {}", e),
+                })
+            }
+        };
+
+        Ok(Some(AccessLog {
+            path,
+            combined,
+            writer: Mutex::new(BufWriter::with_capacity(LOG_BUFFER, file)),
+            reopen,
+            #[cfg(unix)]
+            signal,
+        }))
+    }
+
+    /// Add a line for a response.
+    pub fn log(&self, entry: &Entry) {
+        let field = |f: RequestField| match entry.header.and_then(|h| h.get_header_field(f)) {
+            Some(x) if !x.is_empty() => x.to_string(),
+            _ => "-".to_string(),
+        };
+
+        let host = match entry.addr {
+            Some(addr) => addr.ip().to_string(),
+            None => "-".to_string(),
+        };
+        let request = match entry.header {
+            Some(h) => format!("\"{}\"", escape(h.get_request_line())),
+            None => "\"-\"".to_string(),
+        };
+        let status = match entry.status {
+            StatusCode::Unknown => "-".to_string(),
+            x => (x as u16).to_string(),
+        };
+        let user = match entry.header.and_then(|h| h.get_user()) {
+            Some(x) => escape(x).replace(' ', "+"),
+            None => "-".to_string(),
+        };
+        let bytes = match entry.bytes {
+            0 => "-".to_string(),
+            x => x.to_string(),
+        };
+
+        // The ident field carries the `From` address, nobody runs identd
+        let mut line = format!(
+            "{} {} {} [{}] {} {} {}",
+            host,
+            escape(&field(RequestField::FromField)).replace(' ', "+"),
+            user,
+            date::format_log_date(SystemTime::now()),
+            request,
+            status,
+            bytes
+        );
+        if self.combined {
+            line.push_str(&format!(
+                " \"{}\" \"{}\"",
+                escape(&field(RequestField::Referer)),
+                escape(&field(RequestField::UserAgent))
+            ));
+        }
+        line.push('\n');
+
+        if let Ok(mut writer) = self.writer.lock() {
+            if let Err(e) = writer.write_all(line.as_bytes()) {
+                error!("Could not write the access log! err: {}", e);
+            }
+        }
+    }
+
+    /// Has a SIGHUP asked for the file to be reopened?
+    pub fn reopen_requested(&self) -> bool {
+        self.reopen.load(Ordering::SeqCst)
+    }
+
+    /// Write out buffered lines, and reopen the file if a SIGHUP asked for
+    /// it.
+    pub fn flush(&self) {
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        if let Err(e) = writer.flush() {
+            error!("Could not write the access log! err: {}", e);
+        }
+
+        if self.reopen.swap(false, Ordering::SeqCst) {
+            match open_file(&self.path) {
+                Ok(file) => *writer = BufWriter::with_capacity(LOG_BUFFER, file),
+                Err(e) => error!(
+                    "Could not reopen access log {}! err: {}",
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        hangup_close(self.signal);
+        self.flush();
+    }
+}
+
+// Set `reopen` on SIGHUP, in place of whatever SIGHUP did before.
+#[cfg(unix)]
+fn hangup_open(reopen: &Arc<AtomicBool>) -> std::io::Result<signal_hook::SigId> {
+    let mut hangup = HANGUP.lock().unwrap_or_else(|e| e.into_inner());
+    if hangup.was_default.is_none() {
+        hangup.was_default = Some(hangup_is_default());
+    }
+
+    let signal = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(reopen))?;
+    if let Some(default) = hangup.default.take() {
+        signal_hook::low_level::unregister(default);
+    }
+    hangup.logs += 1;
+    Ok(signal)
+}
+
+// Remove the handler of a closed log. The handler signal-hook installs
+// stays, so with the last log gone it is told to end the process as the
+// default action would.
+#[cfg(unix)]
+fn hangup_close(signal: signal_hook::SigId) {
+    let mut hangup = HANGUP.lock().unwrap_or_else(|e| e.into_inner());
+    signal_hook::low_level::unregister(signal);
+    hangup.logs -= 1;
+
+    if hangup.logs == 0 && hangup.was_default == Some(true) {
+        let always = Arc::new(AtomicBool::new(true));
+        match signal_hook::flag::register_conditional_default(signal_hook::consts::SIGHUP, always) {
+            Ok(default) => hangup.default = Some(default),
+            Err(e) => error!("Could not restore the default action of SIGHUP! {}", e),
+        }
+    }
+}
+
+// Is SIGHUP still handled by its default action, ending the process?
+#[cfg(unix)]
+fn hangup_is_default() -> bool {
+    // Only reads the current action
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(libc::SIGHUP, std::ptr::null(), &mut action) == 0
+            && action.sa_sigaction == libc::SIG_DFL
+    }
+}
+
+fn open_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// Escape what could break a log line: quotes, backslashes and control
+// characters.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
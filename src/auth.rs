@@ -0,0 +1,144 @@
+//! Basic Authentication
+//!
+//! Paths under the `prefix` of an `[[auth]]` table are only served to the
+//! users of its `htpasswd` file, who log in with HTTP Basic authentication.
+//! Passwords are checked against bcrypt (`$2y$`) or SHA-crypt (`$5$`,
+//! `$6$`) hashes. The files are read when the server is built. A request
+//! is checked both for the path it asks for and the path a rewrite serves
+//! it from.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::collections::HashMap;
+use std::fs;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{info, warn};
+
+use crate::configuration::Config;
+use crate::protocol::{RequestField, StatusCode};
+use crate::request::Header;
+use crate::response::Response;
+use crate::TinyHttpError;
+
+/// Hashes a password may be checked against.
+const HASH_PREFIXES: &[&str] = &["$2a$", "$2b$", "$2y$", "$5$", "$6$"];
+
+/// The protected paths of a server.
+pub struct Realms {
+    realms: Vec<Realm>,
+}
+
+struct Realm {
+    prefix: String,
+    name: String,
+    /// User name to password hash
+    users: HashMap<String, String>,
+    /// Hash an unknown user's password is checked against, so they take as
+    /// long to turn away as a known user with a wrong password
+    dummy: Option<String>,
+}
+
+impl Realms {
+    /// Read the `[[auth]]` tables of `config` and their `htpasswd` files.
+    pub fn new(config: &Config) -> Result<Self, TinyHttpError> {
+        let mut realms = Vec::new();
+
+        for auth in config.auth.iter().flatten() {
+            let file = match fs::read_to_string(&auth.htpasswd) {
+                Ok(file) => file,
+                Err(e) => {
+                    return Err(TinyHttpError {
+                        message: format!("Could not read {}! {}", auth.htpasswd, e),
+                    })
+                }
+            };
+
+            let mut users = HashMap::new();
+            for line in file.lines().map(|x| x.trim()) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match line.split_once(':') {
+                    Some((user, hash)) if HASH_PREFIXES.iter().any(|x| hash.starts_with(x)) => {
+                        users.insert(user.to_string(), hash.to_string());
+                    }
+                    _ => warn!(
+                        "Skipped a line of {}, it is not a user with a bcrypt or SHA-crypt hash",
+                        auth.htpasswd
+                    ),
+                }
+            }
+
+            realms.push(Realm {
+                prefix: auth.prefix.clone(),
+                name: auth.realm.clone(),
+                dummy: users.values().next().cloned(),
+                users,
+            });
+        }
+
+        Ok(Realms { realms })
+    }
+
+    /// Check the credentials of a request for a protected path, and note
+    /// the user on it. Answers with 401 Unauthorized if they are missing or
+    /// wrong. A rewritten request is checked again for the path it is
+    /// served from.
+    pub fn check(&self, req: &mut Header, config: &Config) -> Option<Response> {
+        if !req.is_valid() {
+            return None;
+        }
+
+        // The most specific prefix wins
+        let realm = self
+            .realms
+            .iter()
+            .filter(|x| req.get_path().starts_with(&x.prefix))
+            .max_by_key(|x| x.prefix.len())?;
+
+        if let Some((user, password)) = credentials(req) {
+            let (known, hash) = match realm.users.get(&user) {
+                Some(hash) => (true, Some(hash)),
+                None => (false, realm.dummy.as_ref()),
+            };
+            if hash.is_some_and(|x| pwhash::unix::verify(&password, x)) && known {
+                req.set_user(user);
+                return None;
+            }
+            info!(
+                "Failed login of {} to {} for {}",
+                user,
+                realm.name,
+                req.get_path()
+            );
+        }
+
+        let mut res = Response::from_status(StatusCode::Unauthorized);
+        res.set_field(
+            RequestField::WwwAuthenticate,
+            &format!(
+                "Basic realm=\"{}\", charset=\"UTF-8\"",
+                realm.name.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+        );
+        Some(res.complete(config))
+    }
+}
+
+// The user name and password of `Authorization: Basic <base64>`.
+fn credentials(req: &Header) -> Option<(String, String)> {
+    let field = req.get_header_field(RequestField::Authorization)?;
+    let (scheme, encoded) = field.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
@@ -0,0 +1,197 @@
+//! Directory Listings
+//!
+//! A listing of the files in a directory without an index file, made when
+//! `autoindex` is set. It is an HTML page for people or a JSON document for
+//! programs, sorted by name, size or modification time as the query of the
+//! request asks.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::cmp::Ordering;
+use std::time::SystemTime;
+
+use crate::date;
+use crate::request;
+
+/// A file or directory to list.
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sort {
+    Name,
+    Size,
+    Modified,
+}
+
+impl Sort {
+    fn as_str(self) -> &'static str {
+        match self {
+            Sort::Name => "name",
+            Sort::Size => "size",
+            Sort::Modified => "modified",
+        }
+    }
+}
+
+/// List `entries` of the directory at request path `path`. The `sort`,
+/// `order` and `format` parameters of `query` pick the order and whether
+/// it is `html` or `json`, `format` is the default. Returns the media type
+/// and the listing.
+pub fn render(
+    path: &str,
+    mut entries: Vec<Entry>,
+    query: &str,
+    format: &str,
+) -> (&'static str, String) {
+    let mut sort = Sort::Name;
+    let mut descending = false;
+    let mut format = format;
+    for pair in query.split('&') {
+        match pair.split_once('=').unwrap_or((pair, "")) {
+            ("sort", "name") => sort = Sort::Name,
+            ("sort", "size") => sort = Sort::Size,
+            ("sort", "modified") => sort = Sort::Modified,
+            ("order", x) => descending = x == "desc",
+            ("format", x) => format = x,
+            _ => (),
+        }
+    }
+
+    // Directories come first, whatever the order
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            Sort::Name => Ordering::Equal,
+            Sort::Size => a.size.cmp(&b.size),
+            Sort::Modified => a.modified.cmp(&b.modified),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        let order = if descending { order.reverse() } else { order };
+        b.is_dir.cmp(&a.is_dir).then(order)
+    });
+
+    match format {
+        "json" => ("application/json", json(path, &entries)),
+        _ => (
+            "text/html; charset=utf-8",
+            html(path, &entries, sort, descending),
+        ),
+    }
+}
+
+fn html(path: &str, entries: &[Entry], sort: Sort, descending: bool) -> String {
+    let title = format!("Index of {}", escape_html(path));
+    let mut page = format!(
+        concat!(
+            "<!DOCTYPE html>\n",
+            "<html>\n",
+            "  <head>\n",
+            "    <meta charset=\"UTF-8\">\n",
+            "    <title>{}</title>\n",
+            "  </head>\n",
+            "  <body>\n",
+            "    <h1>{}</h1>\n",
+            "    <table>\n",
+            "      <tr>",
+        ),
+        title, title
+    );
+
+    // A heading sorts by its column, the current one flips the order
+    for (column, heading) in &[
+        (Sort::Name, "Name"),
+        (Sort::Size, "Size"),
+        (Sort::Modified, "Last modified"),
+    ] {
+        let order = if *column == sort && !descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        page.push_str(&format!(
+            "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+            column.as_str(),
+            order,
+            heading
+        ));
+    }
+    page.push_str("</tr>\n");
+
+    if path != "/" {
+        page.push_str("      <tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        page.push_str(&format!(
+            "      <tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            request::percent_encode(&entry.name),
+            slash,
+            escape_html(&entry.name),
+            slash,
+            size,
+            entry
+                .modified
+                .map(date::format_iso_date)
+                .unwrap_or_default()
+        ));
+    }
+
+    page.push_str("    </table>\n  </body>\n</html>\n");
+    page
+}
+
+fn json(path: &str, entries: &[Entry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let modified = match entry.modified {
+                Some(time) => format!("\"{}\"", date::format_iso_date(time)),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+                escape_json(&entry.name),
+                if entry.is_dir { "directory" } else { "file" },
+                entry.size,
+                modified
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"path\":\"{}\",\"entries\":[{}]}}\n",
+        escape_json(path),
+        entries.join(",")
+    )
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
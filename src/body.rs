@@ -0,0 +1,199 @@
+//! Response Bodies
+//!
+//! A body is written to the client after the header, a buffer at a time,
+//! so a large file never has to be held in memory. On Linux a file can be
+//! handed to `sendfile` and is copied to the socket by the kernel.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+
+/// The content of a response.
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    /// Content already in memory
+    Bytes(Vec<u8>),
+    /// `len` bytes of `file` starting at `offset`
+    File { file: File, offset: u64, len: u64 },
+    /// `len` bytes read from `reader`
+    Reader {
+        reader: Box<dyn Read + Send>,
+        len: u64,
+    },
+    /// Several bodies sent one after the other
+    Parts(Vec<Body>),
+}
+
+/// Where a response is written. A sink the kernel can copy a file into
+/// directly gives out its descriptor so file bodies can use `sendfile`.
+pub trait Sink: Write {
+    fn sendfile_fd(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl Sink for TcpStream {
+    #[cfg(target_os = "linux")]
+    fn sendfile_fd(&self) -> Option<i32> {
+        use std::os::unix::io::AsRawFd;
+        Some(self.as_raw_fd())
+    }
+}
+
+impl Sink for Vec<u8> {}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Self {
+        Body::Bytes(s.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(s: &str) -> Self {
+        Body::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl Body {
+    /// Number of bytes the body will write.
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } | Body::Reader { len, .. } => *len,
+            Body::Parts(parts) => parts.iter().map(|x| x.len()).sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The whole file at its current size.
+    pub fn file(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Body::File {
+            file,
+            offset: 0,
+            len,
+        })
+    }
+
+    /// Write the body to `sink` using a buffer of `buffer` bytes. Files are
+    /// given to `sendfile` instead when `sendfile` is set and `sink` allows
+    /// it. Returns the number of bytes written.
+    pub fn write_to<S: Sink>(self, sink: &mut S, buffer: usize, sendfile: bool) -> io::Result<u64> {
+        match self {
+            Body::Empty => Ok(0),
+            Body::Bytes(bytes) => {
+                sink.write_all(&bytes)?;
+                Ok(bytes.len() as u64)
+            }
+            Body::File {
+                mut file,
+                offset,
+                len,
+            } => {
+                if sendfile {
+                    if let Some(fd) = sink.sendfile_fd() {
+                        // Anything buffered has to go out first
+                        sink.flush()?;
+                        if let Some(sent) = send_file(&file, fd, offset, len)? {
+                            return Ok(sent);
+                        }
+                    }
+                }
+                file.seek(SeekFrom::Start(offset))?;
+                copy(&mut file.take(len), sink, buffer, len)
+            }
+            Body::Reader { reader, len } => copy(&mut reader.take(len), sink, buffer, len),
+            Body::Parts(parts) => {
+                let mut written = 0;
+                for part in parts {
+                    written += part.write_to(sink, buffer, sendfile)?;
+                }
+                Ok(written)
+            }
+        }
+    }
+}
+
+// Copy exactly `len` bytes, a buffer at a time. The source running out
+// early is an error since the client was promised `len` bytes.
+fn copy<R: Read, W: Write>(src: &mut R, dst: &mut W, buffer: usize, len: u64) -> io::Result<u64> {
+    let mut buf = vec![0u8; buffer.max(1).min(len.max(1) as usize)];
+    let mut written = 0;
+
+    while written < len {
+        let n = match src.read(&mut buf) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "body ended before its length",
+                ))
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buf[..n])?;
+        written += n as u64;
+    }
+
+    Ok(written)
+}
+
+// Send `len` bytes of `file` from `offset` to the socket `fd` with
+// sendfile(2). Returns `None` when nothing was sent because the kernel
+// can not do it for this file, the caller copies it instead.
+#[cfg(target_os = "linux")]
+fn send_file(file: &File, fd: i32, offset: u64, len: u64) -> io::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+
+    // The most Linux moves in one call
+    const MAX_CHUNK: u64 = 0x7fff_f000;
+
+    let mut off = offset as libc::off_t;
+    let mut sent = 0;
+
+    while sent < len {
+        let count = (len - sent).min(MAX_CHUNK) as usize;
+        let n = unsafe { libc::sendfile(fd, file.as_raw_fd(), &mut off, count) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::EINVAL) | Some(libc::ENOSYS) if sent == 0 => return Ok(None),
+                // A write timeout on a blocking socket
+                Some(libc::EAGAIN) => return Err(io::Error::new(ErrorKind::TimedOut, e)),
+                _ => return Err(e),
+            }
+        }
+        if n == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "file ended before its length",
+            ));
+        }
+        sent += n as u64;
+    }
+
+    Ok(Some(sent))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_file(_file: &File, _fd: i32, _offset: u64, _len: u64) -> io::Result<Option<u64>> {
+    Ok(None)
+}
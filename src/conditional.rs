@@ -0,0 +1,420 @@
+//! Conditional Requests
+//!
+//! The validators of a static file, `Last-Modified` and `ETag`, and the
+//! preconditions a client can make on them with `If-Match`,
+//! `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since`. See
+//! RFC 7232 for more information.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::configuration::Config;
+use crate::date;
+use crate::protocol::{RequestField, RequestMethod, StatusCode};
+use crate::request::Header;
+
+/// How `ETag`s are generated when `etag` is not set.
+pub const DEFAULT_ETAG: &str = "metadata";
+
+/// Most file hashes kept for `etag = 'hash'`. The cache is emptied when it
+/// fills up.
+const MAX_HASHES: usize = 1024;
+
+// A file's path, inode, size and modification time. A file is hashed again
+// once any of them change.
+type FileKey = (PathBuf, u64, u64, u128);
+
+// Hashes of files already read, so a file is not read again for every
+// request.
+static HASHES: OnceLock<Mutex<HashMap<FileKey, String>>> = OnceLock::new();
+
+/// The current state of a resource, as far as a client can tell.
+pub struct Validators {
+    pub modified: Option<SystemTime>,
+    pub etag: Option<String>,
+}
+
+impl Validators {
+    /// Get the validators of the file at `path`. The `etag` option picks
+    /// how the `ETag` is made: `metadata` from the inode, size and
+    /// modification time, `hash` from the content, or `off` for none.
+    pub fn new(path: &Path, meta: &Metadata, config: &Config) -> Self {
+        let modified = meta.modified().ok();
+        let mode = match &config.etag {
+            Some(x) => x.as_str(),
+            None => DEFAULT_ETAG,
+        };
+
+        let nanos = match modified.map(|t| t.duration_since(SystemTime::UNIX_EPOCH)) {
+            Some(Ok(d)) => d.as_nanos(),
+            _ => 0,
+        };
+        let etag = match mode {
+            "off" => None,
+            "hash" => {
+                let key = (path.to_path_buf(), inode(meta), meta.len(), nanos);
+                cached_hash(key).map(|hash| format!("\"{}\"", hash))
+            }
+            _ => Some(format!(
+                "\"{:x}-{:x}-{:x}\"",
+                inode(meta),
+                meta.len(),
+                nanos
+            )),
+        };
+
+        Validators { modified, etag }
+    }
+}
+
+// The hash of the file described by `key`, from the cache if the file has
+// not changed since it was hashed.
+fn cached_hash(key: FileKey) -> Option<String> {
+    let hashes = HASHES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(hash) = hashes.lock().ok()?.get(&key) {
+        return Some(hash.clone());
+    }
+
+    // Not held while the file is read, other requests need not wait
+    let hash = hash_file(&key.0).ok()?;
+    let mut hashes = hashes.lock().ok()?;
+    if hashes.len() >= MAX_HASHES {
+        hashes.clear();
+    }
+    hashes.insert(key, hash.clone());
+    Some(hash)
+}
+
+// The first 128 bits of the SHA-256 of the file at `path` in hex, read
+// without holding it in memory whole.
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => {
+                let digest = hasher.finalize();
+                return Ok(digest[..16].iter().map(|b| format!("{:02x}", b)).collect());
+            }
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Evaluate the preconditions of `req` against `current`, the state of the
+/// resource or `None` if it does not exist. Returns the status to answer
+/// with instead of carrying out the request: 304 Not Modified for a GET or
+/// HEAD whose copy is still good, 412 Precondition Failed otherwise. The
+/// order follows RFC 7232, Section 6.
+pub fn evaluate(req: &Header, current: Option<&Validators>) -> Option<StatusCode> {
+    let method = req.get_method();
+    let safe = method == RequestMethod::Get || method == RequestMethod::Head;
+    let etag = current.and_then(|x| x.etag.as_deref());
+    let modified = current.and_then(|x| x.modified);
+
+    if let Some(tags) = req.get_header_field(RequestField::IfMatch) {
+        if current.is_none() || !tags_match(tags, etag, true) {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if let Some(since) = header_date(req, RequestField::IfUnmodifiedSince) {
+        if let Some(modified) = modified {
+            if secs(modified) > secs(since) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        }
+    }
+
+    if let Some(tags) = req.get_header_field(RequestField::IfNoneMatch) {
+        if current.is_some() && tags_match(tags, etag, false) {
+            if safe {
+                return Some(StatusCode::NotModified);
+            }
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if safe {
+        // Dates in the future are invalid, RFC 1945 Section 10.9
+        let since = header_date(req, RequestField::IfModifiedSince)
+            .filter(|since| *since <= SystemTime::now());
+        if let (Some(since), Some(modified)) = (since, modified) {
+            if secs(modified) <= secs(since) {
+                return Some(StatusCode::NotModified);
+            }
+        }
+    }
+
+    None
+}
+
+/// Does `req` make a precondition a method other than GET or HEAD has to
+/// meet? `If-Modified-Since` only applies to GET and HEAD.
+pub fn has_preconditions(req: &Header) -> bool {
+    [
+        RequestField::IfMatch,
+        RequestField::IfNoneMatch,
+        RequestField::IfUnmodifiedSince,
+    ]
+    .iter()
+    .any(|x| req.get_header_field(*x).is_some())
+}
+
+/// Should the `Range` of `req` be honored? Without `If-Range` it always
+/// is. With it, only if the field names the current `ETag` (compared
+/// strongly) or exactly the current `Last-Modified` date, otherwise the
+/// client's copy is stale and the whole resource is sent.
+pub fn if_range(req: &Header, current: &Validators) -> bool {
+    let value = match req.get_header_field(RequestField::IfRange) {
+        Some(value) => value.trim(),
+        None => return true,
+    };
+
+    if value.starts_with('"') || value.starts_with("W/") {
+        return tags_match(value, current.etag.as_deref(), true);
+    }
+
+    match (date::parse_http_date(value), current.modified) {
+        (Some(date), Some(modified)) => secs(date) == secs(modified),
+        _ => false,
+    }
+}
+
+// Does `etag` match one of the comma separated entity-tags in `tags`? `*`
+// matches any existing resource. A strong comparison never matches weak
+// (`W/`) tags, a weak one ignores the difference.
+fn tags_match(tags: &str, etag: Option<&str>, strong: bool) -> bool {
+    if tags.trim() == "*" {
+        return true;
+    }
+
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+
+    tags.split(',').map(|x| x.trim()).any(|tag| {
+        if strong {
+            !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag
+        } else {
+            tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        }
+    })
+}
+
+fn header_date(req: &Header, field: RequestField) -> Option<SystemTime> {
+    req.get_header_field(field).and_then(date::parse_http_date)
+}
+
+// HTTP dates only carry whole seconds
+fn secs(t: SystemTime) -> u64 {
+    match t.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(unix)]
+fn inode(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_meta: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::time::Duration;
+
+    // A request made with `method` and the `fields` lines.
+    fn request(method: &str, fields: &[&str]) -> Header {
+        let mut head = format!("{} / HTTP/1.0\r\n", method);
+        for field in fields {
+            head.push_str(field);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        Header::new(head.as_bytes())
+    }
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    fn modified() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777)
+    }
+
+    fn current() -> Validators {
+        Validators {
+            modified: Some(modified()),
+            etag: Some("\"abc\"".to_string()),
+        }
+    }
+
+    #[test]
+    fn strong_and_weak_tags() {
+        assert!(tags_match("\"abc\"", Some("\"abc\""), true));
+        assert!(tags_match("\"x\", \"abc\"", Some("\"abc\""), true));
+        assert!(!tags_match("W/\"abc\"", Some("\"abc\""), true));
+        assert!(!tags_match("\"abc\"", Some("W/\"abc\""), true));
+        assert!(!tags_match("\"abd\"", Some("\"abc\""), true));
+
+        assert!(tags_match("W/\"abc\"", Some("\"abc\""), false));
+        assert!(tags_match("\"abc\"", Some("W/\"abc\""), false));
+        assert!(!tags_match("W/\"abd\"", Some("\"abc\""), false));
+    }
+
+    #[test]
+    fn star_matches_any_resource() {
+        assert!(tags_match("*", Some("\"abc\""), true));
+        assert!(tags_match(" * ", None, false));
+        assert!(!tags_match("\"abc\"", None, false));
+    }
+
+    #[test]
+    fn if_none_match() {
+        let get = request("GET", &["If-None-Match: W/\"abc\""]);
+        assert_eq!(
+            evaluate(&get, Some(&current())),
+            Some(StatusCode::NotModified)
+        );
+        let get = request("GET", &["If-None-Match: \"abd\""]);
+        assert_eq!(evaluate(&get, Some(&current())), None);
+
+        let post = request("POST", &["If-None-Match: *"]);
+        assert_eq!(
+            evaluate(&post, Some(&current())),
+            Some(StatusCode::PreconditionFailed)
+        );
+        // Creating a resource that does not exist yet
+        assert_eq!(evaluate(&post, None), None);
+    }
+
+    #[test]
+    fn if_match() {
+        let post = request("POST", &["If-Match: \"abc\""]);
+        assert_eq!(evaluate(&post, Some(&current())), None);
+        assert_eq!(evaluate(&post, None), Some(StatusCode::PreconditionFailed));
+
+        let post = request("POST", &["If-Match: W/\"abc\""]);
+        assert_eq!(
+            evaluate(&post, Some(&current())),
+            Some(StatusCode::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn dates() {
+        let get = request("GET", &["If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"]);
+        assert_eq!(
+            evaluate(&get, Some(&current())),
+            Some(StatusCode::NotModified)
+        );
+        let get = request("GET", &["If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT"]);
+        assert_eq!(evaluate(&get, Some(&current())), None);
+        // Invalid dates are ignored
+        let get = request("GET", &["If-Modified-Since: yesterday"]);
+        assert_eq!(evaluate(&get, Some(&current())), None);
+        // If-None-Match takes the place of If-Modified-Since
+        let get = request(
+            "GET",
+            &[
+                "If-None-Match: \"abd\"",
+                "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT",
+            ],
+        );
+        assert_eq!(evaluate(&get, Some(&current())), None);
+
+        let post = request(
+            "POST",
+            &["If-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT"],
+        );
+        assert_eq!(
+            evaluate(&post, Some(&current())),
+            Some(StatusCode::PreconditionFailed)
+        );
+        let post = request(
+            "POST",
+            &["If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT"],
+        );
+        assert_eq!(evaluate(&post, Some(&current())), None);
+    }
+
+    #[test]
+    fn preconditions_present() {
+        assert!(!has_preconditions(&request("POST", &[])));
+        assert!(!has_preconditions(&request(
+            "POST",
+            &["If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"]
+        )));
+        assert!(has_preconditions(&request("POST", &["If-Match: *"])));
+        assert!(has_preconditions(&request("PUT", &["if-none-match: *"])));
+    }
+
+    #[test]
+    fn if_range() {
+        assert!(super::if_range(&request("GET", &[]), &current()));
+        assert!(super::if_range(
+            &request("GET", &["If-Range: \"abc\""]),
+            &current()
+        ));
+        assert!(!super::if_range(
+            &request("GET", &["If-Range: W/\"abc\""]),
+            &current()
+        ));
+        assert!(!super::if_range(
+            &request("GET", &["If-Range: \"abd\""]),
+            &current()
+        ));
+        assert!(super::if_range(
+            &request("GET", &["If-Range: Sun, 06 Nov 1994 08:49:37 GMT"]),
+            &current()
+        ));
+        assert!(!super::if_range(
+            &request("GET", &["If-Range: Sun, 06 Nov 1994 08:49:38 GMT"]),
+            &current()
+        ));
+        assert!(!super::if_range(
+            &request("GET", &["If-Range: soon"]),
+            &current()
+        ));
+    }
+
+    #[test]
+    fn content_hash() {
+        let path = env::temp_dir().join(format!("tiny_http_etag_{}", process::id()));
+        fs::write(&path, "hello").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e"
+        );
+
+        let config = Config {
+            etag: Some("hash".to_string()),
+            ..Config::default()
+        };
+        let meta = fs::metadata(&path).unwrap();
+        let validators = Validators::new(&path, &meta, &config);
+        assert_eq!(
+            validators.etag.as_deref(),
+            Some("\"2cf24dba5fb0a30e26e83b2ac5b9e29e\"")
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,26 +1,218 @@
+//! Server Configuration
+//!
+//! Options are usually read from a TOML file such as the `Config.toml`
+//! in the root directory, see it for a description of every option. Any
+//! option left out of the file takes its default value.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
 use ::std::io::prelude::*;
-use lazy_static::lazy_static;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
+use std::path::Path;
+
+use crate::conditional::DEFAULT_ETAG;
+use crate::mime::DEFAULT_MIME_TYPE;
+
+/// Largest request accepted when `max_buffer` is not set.
+pub const DEFAULT_MAX_BUFFER: usize = 1_048_576;
+/// Number of workers serving connections when `worker_threads` is not set.
+pub const DEFAULT_WORKER_THREADS: usize = 8;
+/// Connections allowed to wait for a worker when `accept_queue` is not set.
+pub const DEFAULT_ACCEPT_QUEUE: usize = 64;
+/// Seconds a turned away client is asked to wait when `retry_after` is not set.
+pub const DEFAULT_RETRY_AFTER: u64 = 1;
+/// Seconds given to running connections on shutdown when `drain_timeout`
+/// is not set.
+pub const DEFAULT_DRAIN_TIMEOUT: u64 = 10;
+/// Seconds a persistent connection waits for the next request when
+/// `keep_alive_timeout` is not set.
+pub const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 5;
+/// Requests served on one connection when `keep_alive_max` is not set.
+pub const DEFAULT_KEEP_ALIVE_MAX: usize = 100;
+/// Seconds a write to a client may block when `write_timeout` is not set.
+pub const DEFAULT_WRITE_TIMEOUT: u64 = 30;
+/// Bytes of a body copied to the client at a time when `write_buffer` is
+/// not set.
+pub const DEFAULT_WRITE_BUFFER: usize = 65_536;
+/// Bytes of an uploaded part kept in memory when `upload_in_memory` is not
+/// set, larger parts go to a temporary file.
+pub const DEFAULT_UPLOAD_IN_MEMORY: usize = 65_536;
+/// Largest uploaded part when `upload_max_part` is not set.
+pub const DEFAULT_UPLOAD_MAX_PART: u64 = 16_777_216;
+/// Largest `multipart/form-data` body when `upload_max_total` is not set.
+pub const DEFAULT_UPLOAD_MAX_TOTAL: u64 = 67_108_864;
+/// Format of the access log when `access_log_format` is not set.
+pub const DEFAULT_ACCESS_LOG_FORMAT: &str = "combined";
+/// Status of a redirect when its `status` is not set.
+pub const DEFAULT_REDIRECT_STATUS: u16 = 301;
+/// Format of directory listings when `autoindex_format` is not set.
+pub const DEFAULT_AUTOINDEX_FORMAT: &str = "html";
+/// Where the error log goes when `error_log` is not set.
+pub const DEFAULT_ERROR_LOG: &str = "stderr";
+/// Least severe messages logged when `log_level` is not set.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+/// Sent as `Server` when `server_token` is not set.
+pub const DEFAULT_SERVER_TOKEN: &str = concat!("TinyHTTP/", env!("CARGO_PKG_VERSION"));
+
+/// A certificate for some of the names a client may ask for.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Certificate {
+    /// Server names, `*.` in front matches any one label
+    pub names: Vec<String>,
+    /// PEM file with the certificate chain
+    pub cert_file: String,
+    /// PEM file with the private key
+    pub key_file: String,
+}
+
+/// A site served for the names in `server_name` and `aliases`, from its
+/// own `doc_root`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct VirtualHost {
+    /// Name of the site, `*.` in front matches any subdomain
+    pub server_name: String,
+    /// Other names of the site, they may be wildcards too
+    pub aliases: Option<Vec<String>>,
+    pub doc_root: String,
+    /// The `root_file` of the site, the server's when not set
+    pub root_file: Option<String>,
+    /// Status code to the page under this `doc_root`
+    pub error_pages: Option<HashMap<String, String>>,
+    /// Serve requests for names no virtual host has
+    pub default: Option<bool>,
+}
 
-lazy_static! {
-    // Global configuration variable.
-    pub static ref CONFIG: Config = Config::new();
+/// A rule matching request paths, by exactly one of `exact`, `prefix` or
+/// `regex`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rule {
+    /// Matches this path only
+    pub exact: Option<String>,
+    /// Matches paths starting with this, the rest is added to `to`
+    pub prefix: Option<String>,
+    /// Matches paths this finds, `$1` or `${name}` in `to` are its groups
+    pub regex: Option<String>,
+    /// Where matching paths go
+    pub to: String,
+    /// Status of a redirect: 301, 302, 303, 307 or 308
+    pub status: Option<u16>,
 }
 
-/// Configure options. These are pulled from Config.toml
+/// Paths only served to the users of an htpasswd file, who log in with
+/// HTTP Basic authentication.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthRealm {
+    /// Paths starting with this are protected
+    pub prefix: String,
+    /// Name shown to the user when asked to log in
+    pub realm: String,
+    /// File of `user:hash` lines, bcrypt or SHA-crypt hashes
+    pub htpasswd: String,
+}
+
+/// Error produced when a configuration can not be read or parsed.
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub message: String,
+}
+
+/// Configure options. These are usually pulled from Config.toml
 /// in the root directory.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     pub host: String,
     pub port: u16,
     pub doc_root: String,
+    /// Image extensions the server will serve
     pub image_list: Vec<String>,
+    /// Other file extensions the server will serve
     pub file_list: Vec<String>,
+    /// Extensions that are never served, even with `serve_all`
+    pub deny_list: Option<Vec<String>>,
+    /// Serve any extension not on `deny_list`
+    pub serve_all: Option<bool>,
+    /// Serve files behind symbolic links that lead outside of `doc_root`
+    pub follow_symlinks: Option<bool>,
+    /// How `ETag`s are made: `metadata`, `hash` or `off`
+    pub etag: Option<String>,
     pub default_root_file: Option<String>,
+    /// Index file served for a directory, `/` included
     pub root_file: Option<String>,
+    /// List the files of a directory without an index file
+    pub autoindex: Option<bool>,
+    /// Format of those listings: `html` or `json`
+    pub autoindex_format: Option<String>,
+    /// Largest request (header and body) accepted from a client
     pub max_buffer: Option<usize>,
+    /// Page sent with a 404 Not Found when `error_pages` has none
     pub custom_404: Option<String>,
+    /// Status code to the page under `doc_root` sent with that error
+    pub error_pages: Option<HashMap<String, String>>,
+    /// Extension to media type, overrides and extends the built in table
+    pub mime_types: Option<HashMap<String, String>>,
+    /// Media type of files with an unknown extension
+    pub default_mime_type: Option<String>,
+    /// Number of threads serving connections
+    pub worker_threads: Option<usize>,
+    /// Connections waiting for a free worker before new ones get a 503
+    pub accept_queue: Option<usize>,
+    /// Seconds sent in `Retry-After` with a 503 Service Unavailable
+    pub retry_after: Option<u64>,
+    /// Seconds running connections get to finish when the server shuts down
+    pub drain_timeout: Option<u64>,
+    /// Seconds a persistent connection may sit idle between requests
+    pub keep_alive_timeout: Option<u64>,
+    /// Most requests served on a single connection
+    pub keep_alive_max: Option<usize>,
+    /// Seconds a client may go without taking any of a response
+    pub write_timeout: Option<u64>,
+    /// Size of the buffer a body is copied to the client with
+    pub write_buffer: Option<usize>,
+    /// Let the kernel copy files to the client where it can (Linux only)
+    pub sendfile: Option<bool>,
+    /// Sent as `Server` with every response, left out when empty
+    pub server_token: Option<String>,
+    /// File every response is logged to, no access log when not set
+    pub access_log: Option<String>,
+    /// Format of the access log: `common` or `combined`
+    pub access_log_format: Option<String>,
+    /// Where uploaded parts too large for memory are stored
+    pub upload_dir: Option<String>,
+    /// Bytes of an uploaded part kept in memory
+    pub upload_in_memory: Option<usize>,
+    /// Largest part of a `multipart/form-data` body
+    pub upload_max_part: Option<u64>,
+    /// Largest `multipart/form-data` body, in place of `max_buffer`
+    pub upload_max_total: Option<u64>,
+    /// PEM file with the certificate chain, HTTPS is served when set
+    pub cert_file: Option<String>,
+    /// PEM file with the private key of `cert_file`
+    pub key_file: Option<String>,
+    /// Port HTTPS is served on alongside HTTP, `port` serves HTTPS if not set
+    pub tls_port: Option<u16>,
+    /// Redirect requests for HTTP on `port` to HTTPS on `tls_port`
+    pub https_redirect: Option<bool>,
+    /// Certificates picked by the name the client asks for (SNI)
+    pub certificate: Option<Vec<Certificate>>,
+    /// Sites picked by the host a request is for
+    pub vhost: Option<Vec<VirtualHost>>,
+    /// Paths the client is sent elsewhere for
+    pub redirect: Option<Vec<Rule>>,
+    /// Paths served from another path without the client knowing
+    pub rewrite: Option<Vec<Rule>>,
+    /// Paths only served to users who log in
+    pub auth: Option<Vec<AuthRealm>>,
+    /// Where errors are logged: `stderr`, `stdout` or a file
+    pub error_log: Option<String>,
+    /// Least severe messages logged: `error`, `warn`, `info`, `debug`,
+    /// `trace` or `off`
+    pub log_level: Option<String>,
+    /// Log the header of every request at the `debug` level
     pub print_header_information: Option<bool>,
 }
 
@@ -31,31 +223,89 @@ impl Default for Config {
             host: "127.0.0.1".to_string(),
             port: 8080,
             doc_root: "http".to_string(),
-            image_list: Vec::<String>::new(),
-            file_list: Vec::<String>::new(),
+            image_list: ["ico", "jpeg", "jpg", "gif", "png"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            file_list: ["html", "htm", "css", "js", "txt"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            deny_list: None,
+            serve_all: Some(false),
+            follow_symlinks: Some(false),
+            etag: Some(DEFAULT_ETAG.to_string()),
             default_root_file: Some("index.html".to_string()),
             root_file: None,
-            max_buffer: Some(2048),
+            autoindex: Some(false),
+            autoindex_format: Some(DEFAULT_AUTOINDEX_FORMAT.to_string()),
+            max_buffer: Some(DEFAULT_MAX_BUFFER),
             custom_404: None,
+            error_pages: None,
+            mime_types: None,
+            default_mime_type: Some(DEFAULT_MIME_TYPE.to_string()),
+            worker_threads: Some(DEFAULT_WORKER_THREADS),
+            accept_queue: Some(DEFAULT_ACCEPT_QUEUE),
+            retry_after: Some(DEFAULT_RETRY_AFTER),
+            drain_timeout: Some(DEFAULT_DRAIN_TIMEOUT),
+            keep_alive_timeout: Some(DEFAULT_KEEP_ALIVE_TIMEOUT),
+            keep_alive_max: Some(DEFAULT_KEEP_ALIVE_MAX),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+            write_buffer: Some(DEFAULT_WRITE_BUFFER),
+            sendfile: Some(true),
+            server_token: Some(DEFAULT_SERVER_TOKEN.to_string()),
+            access_log: None,
+            access_log_format: Some(DEFAULT_ACCESS_LOG_FORMAT.to_string()),
+            upload_dir: None,
+            upload_in_memory: Some(DEFAULT_UPLOAD_IN_MEMORY),
+            upload_max_part: Some(DEFAULT_UPLOAD_MAX_PART),
+            upload_max_total: Some(DEFAULT_UPLOAD_MAX_TOTAL),
+            cert_file: None,
+            key_file: None,
+            tls_port: None,
+            https_redirect: Some(false),
+            certificate: None,
+            vhost: None,
+            redirect: None,
+            rewrite: None,
+            auth: None,
+            error_log: Some(DEFAULT_ERROR_LOG.to_string()),
+            log_level: Some(DEFAULT_LOG_LEVEL.to_string()),
             print_header_information: Some(false),
         }
     }
 }
 
 impl Config {
-    fn new() -> Self {
-        let mut config = Config::default();
-
-        let mut file = match File::open("Config.toml") {
+    /// Read the configuration from the TOML file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut file = match File::open(path) {
             Ok(file) => file,
-            Err(_) => return config,
+            Err(e) => {
+                return Err(ConfigError {
+                    message: format!("Could not open {}! {}", path.display(), e),
+                })
+            }
         };
 
         let mut c = String::new();
-        file.read_to_string(&mut c).unwrap();
+        if let Err(e) = file.read_to_string(&mut c) {
+            return Err(ConfigError {
+                message: format!("Could not read {}! {}", path.display(), e),
+            });
+        }
 
-        config = toml::from_str(&c).unwrap();
+        Config::from_toml(&c)
+    }
 
-        config
+    /// Parse the configuration from a TOML document.
+    pub fn from_toml(c: &str) -> Result<Self, ConfigError> {
+        match toml::from_str(c) {
+            Ok(config) => Ok(config),
+            Err(e) => Err(ConfigError {
+                message: format!("Invalid configuration! {}", e),
+            }),
+        }
     }
 }
@@ -0,0 +1,119 @@
+//! HTTP Dates
+//!
+//! HTTP/1.0 sends dates in RFC 1123 format, but clients may also use the
+//! older RFC 850 and asctime formats. See RFC 1945, Section 3.3 Date/Time
+//! Formats.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::time::SystemTime;
+
+/// Date formats accepted from a client, preferred format first.
+const DATE_FORMATS: &[&str] = &[
+    // RFC 1123: Sun, 06 Nov 1994 08:49:37 GMT
+    "%a, %d %b %Y %H:%M:%S GMT",
+    // RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+    "%A, %d-%b-%y %H:%M:%S GMT",
+    // asctime: Sun Nov  6 08:49:37 1994
+    "%a %b %e %H:%M:%S %Y",
+];
+
+/// Format `time` as an RFC 1123 date, the format servers must send.
+pub fn format_http_date(time: SystemTime) -> String {
+    let utc_dt: DateTime<Utc> = DateTime::from(time);
+    format!("{}", utc_dt.format(DATE_FORMATS[0]))
+}
+
+/// Format `time` the way access logs do: `10/Oct/2000:13:55:36 +0000`.
+pub fn format_log_date(time: SystemTime) -> String {
+    let utc_dt: DateTime<Utc> = DateTime::from(time);
+    format!("{}", utc_dt.format("%d/%b/%Y:%H:%M:%S %z"))
+}
+
+/// Format `time` as an ISO 8601 date: `2000-10-10T13:55:36Z`.
+pub fn format_iso_date(time: SystemTime) -> String {
+    let utc_dt: DateTime<Utc> = DateTime::from(time);
+    format!("{}", utc_dt.format("%Y-%m-%dT%H:%M:%SZ"))
+}
+
+/// Parse a date in any of the three HTTP formats. Returns `None` if the
+/// date is in none of them.
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    DATE_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .map(|dt| SystemTime::from(dt.and_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    fn example() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777)
+    }
+
+    #[test]
+    fn rfc_1123() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(example())
+        );
+        assert_eq!(
+            parse_http_date("  Sun, 06 Nov 1994 08:49:37 GMT "),
+            Some(example())
+        );
+    }
+
+    #[test]
+    fn rfc_850() {
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(example())
+        );
+    }
+
+    #[test]
+    fn asctime() {
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(example()));
+        assert_eq!(
+            parse_http_date("Thu Nov 17 08:49:37 1994"),
+            Some(example() + Duration::from_secs(11 * 86_400))
+        );
+    }
+
+    #[test]
+    fn malformed_dates() {
+        for s in &[
+            "",
+            "yesterday",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 08:49:37 PST",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 25:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Mon, 06 Nov 1994 08:49:37 GMT",
+            "1994-11-06T08:49:37Z",
+            "784111777",
+        ] {
+            assert_eq!(parse_http_date(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(format_http_date(example()), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format_log_date(example()), "06/Nov/1994:08:49:37 +0000");
+        assert_eq!(format_iso_date(example()), "1994-11-06T08:49:37Z");
+        assert_eq!(
+            parse_http_date(&format_http_date(example())),
+            Some(example())
+        );
+    }
+}
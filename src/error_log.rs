@@ -0,0 +1,102 @@
+//! Error Log
+//!
+//! The server reports what goes wrong, and at the `debug` level what it is
+//! doing, through the `log` facade. Unless the program has set a logger of
+//! its own, the first server built sets this one, which writes messages of
+//! `log_level` and above to `error_log`.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::configuration::{Config, DEFAULT_ERROR_LOG, DEFAULT_LOG_LEVEL};
+use crate::TinyHttpError;
+
+struct ErrorLog {
+    level: LevelFilter,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for ErrorLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Ok(mut out) = self.out.lock() {
+            // Nowhere left to report a failure to
+            let _ = writeln!(
+                out,
+                "[{} {} {}] {}",
+                Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
+
+/// Set the error log described by `error_log` and `log_level` as the
+/// logger of the program, if it has none yet. `error_log` is `stderr`,
+/// `stdout` or the path of a file to append to.
+pub fn init(config: &Config) -> Result<(), TinyHttpError> {
+    let level = match &config.log_level {
+        Some(x) => x.as_str(),
+        None => DEFAULT_LOG_LEVEL,
+    };
+    let level = match level.parse::<LevelFilter>() {
+        Ok(level) => level,
+        Err(_) => {
+            return Err(TinyHttpError {
+                message: format!("Unknown log_level {}", level),
+            })
+        }
+    };
+
+    let destination = match &config.error_log {
+        Some(x) => x.as_str(),
+        None => DEFAULT_ERROR_LOG,
+    };
+    let out: Box<dyn Write + Send> = match destination {
+        "stderr" => Box::new(io::stderr()),
+        "stdout" => Box::new(io::stdout()),
+        path => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                return Err(TinyHttpError {
+                    message: format!("Could not open error log {}! {}", path, e),
+                })
+            }
+        },
+    };
+
+    // Fails when a logger is already set, which is then left alone
+    if log::set_boxed_logger(Box::new(ErrorLog {
+        level,
+        out: Mutex::new(out),
+    }))
+    .is_ok()
+    {
+        log::set_max_level(level);
+    }
+
+    Ok(())
+}
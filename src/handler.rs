@@ -0,0 +1,38 @@
+//! Request Handlers
+//!
+//! Requests the server does not answer from `doc_root` are given to a
+//! handler supplied by the user, which builds the `Response` itself.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use crate::request::Header;
+use crate::response::Response;
+
+/// Answers a request. Any `Fn(&Header) -> Response` is a handler.
+///
+/// ```no_run
+/// use tiny_http::{Header, Response, Server, StatusCode};
+///
+/// let server = tiny_http::Server::builder()
+///     .post_handler(|req: &Header| {
+///         let mut res = Response::from_status(StatusCode::OK);
+///         res.set_body("text/plain", format!("{:?}", req.post_data()));
+///         res
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: &Header) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Header) -> Response + Send + Sync,
+{
+    fn handle(&self, req: &Header) -> Response {
+        self(req)
+    }
+}
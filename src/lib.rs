@@ -1,89 +1,73 @@
-//! Todo Move this to lib.rs
+//! TinyHTTP
+//!
+//! A quasi HTTP/1.0 server. Run it with the options in `Config.toml` by
+//! calling `tiny_http()`, or build a `Server` with its own configuration.
 //!
 //! Greg Hairfield
 //! CS410P Rust Programming
 //! Spring 2021
 
-mod configuration;
+mod access_log;
+mod auth;
+mod autoindex;
+mod body;
+mod conditional;
+pub mod configuration;
+mod date;
+mod error_log;
+mod handler;
+mod middleware;
+mod mime;
+mod multipart;
+mod pool;
 mod protocol;
+mod range;
 mod request;
 mod response;
+mod router;
+mod rules;
+mod server;
+#[cfg(feature = "tls")]
+mod tls;
+mod vhost;
+
+pub use crate::body::Body;
+pub use crate::configuration::Config;
+pub use crate::handler::Handler;
+pub use crate::middleware::Middleware;
+pub use crate::multipart::{Multipart, Part};
+pub use crate::protocol::{RequestField, RequestMethod, RequestVersion, StatusCode};
+pub use crate::request::{Header, PostData};
+pub use crate::response::Response;
+pub use crate::router::Router;
+pub use crate::server::{Server, ServerBuilder, ServerHandle};
 
-use crate::configuration::CONFIG;
-use crate::request::Header;
-use crate::response::Response;
-use std::io::{Read, Write};
-use std::net::{Shutdown, TcpListener, TcpStream};
-use std::thread;
+use std::path::Path;
 
 type Result<T> = std::result::Result<T, TinyHttpError>;
 
-pub struct TinyHttpError;
+/// Where `tiny_http` and `start` look for their configuration.
+const CONFIG_FILE: &str = "Config.toml";
+
+#[derive(Debug)]
+pub struct TinyHttpError {
+    pub message: String,
+}
 
 /// Main entry point of the server. All configuration is done via `Config.toml`
-/// located in the root directory.
+/// located in the root directory. This blocks for as long as the server runs.
 pub fn tiny_http() -> Result<()> {
-    //println!("Config is: {:?}", *CONFIG);
-
-    match listen() {
-        Ok(_) => Ok(()),
-        Err(_) => Err(TinyHttpError),
-    }
+    start()?.wait()
 }
 
-// Listen for incomming connections from a client. Once a connection is
-// established a new thread (per connection).
-fn listen() -> Result<()> {
-    let listen = TcpListener::bind(format!("{}:{}", CONFIG.host, CONFIG.port)).unwrap();
-
-    // TODO
-    //  listen.set_ttl(X)
-    //  listen.set_nonblocking(true).expect("Cannot set non-blocking")
-
-    for stream in listen.incoming() {
-        match stream {
-            Ok(stream) => {
-                thread::spawn(move || {
-                    new_connection(stream);
-                });
-            }
-            Err(e) => {
-                // TODO log error
-                println!("Error connecting to client! {}", e);
-            }
-        }
+/// Start the server configured by `Config.toml` in the background and
+/// return a handle to control it. Without a `Config.toml` the defaults
+/// are used.
+pub fn start() -> Result<ServerHandle> {
+    let mut builder = Server::builder();
+    if Path::new(CONFIG_FILE).exists() {
+        builder = builder.config_file(CONFIG_FILE);
     }
-    Ok(())
-}
-
-fn new_connection(mut conn: TcpStream) {
-    println!("New connection from {}", conn.peer_addr().unwrap());
-
-    let mut buf = [0_u8; 2048];
-    let result = conn.read(&mut buf);
 
-    match result {
-        Ok(_size) => {
-            // TODO Who knows if we are going to need size yet??
-            let header = Header::new(&buf);
-            let mut res = Response::new(&header);
-            if let Some(x) = CONFIG.print_header_information {
-                if x {
-                    header.print();
-                }
-            }
-            let r = res.respond();
-            conn.write_all(&r).unwrap();
-            conn.flush().unwrap();
-            println!("----Responded----");
-        }
-        Err(e) => {
-            println!(
-                "An error occured while reading the stream! ip: {}, err: {}",
-                conn.peer_addr().unwrap(),
-                e
-            );
-            conn.shutdown(Shutdown::Both).unwrap();
-        }
-    };
+    builder.build()?.start()
 }
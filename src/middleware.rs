@@ -0,0 +1,43 @@
+//! Middleware
+//!
+//! Behavior shared by every request, such as authentication, logging or
+//! extra header fields, plugged in around the handling of a request.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use crate::request::Header;
+use crate::response::Response;
+
+/// Hooks run around every request with a valid header. The `before` hooks
+/// run in the order the middleware was added to the server, the `after`
+/// hooks in the opposite order.
+///
+/// ```no_run
+/// use tiny_http::{Header, Middleware, Response, Server};
+///
+/// struct Cors;
+///
+/// impl Middleware for Cors {
+///     fn after(&self, _req: &Header, res: &mut Response) {
+///         res.set_header("Access-Control-Allow-Origin", "*");
+///     }
+/// }
+///
+/// let server = Server::builder().middleware(Cors).build().unwrap();
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Look at, or change, the request before it is handled. Returning a
+    /// response answers the request with it: neither the handler nor the
+    /// `before` hooks of later middleware run.
+    fn before(&self, _req: &mut Header) -> Option<Response> {
+        None
+    }
+
+    /// Look at, or change, the response before it is sent. Runs for every
+    /// middleware whose `before` ran, including one that answered the
+    /// request itself. `Content-Length` is set from the body after the
+    /// hooks, so the body can be replaced, for example compressed.
+    fn after(&self, _req: &Header, _res: &mut Response) {}
+}
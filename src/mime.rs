@@ -0,0 +1,92 @@
+//! Media Types
+//!
+//! Finds the `Content-Type` of a resource from its file extension. The
+//! built in table covers the common web formats, `[mime_types]` in the
+//! configuration can override or extend it.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::path::Path;
+
+use crate::configuration::Config;
+
+/// Media type of files whose extension is not known, unless
+/// `default_mime_type` is set.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Extension to media type map used when the configuration does not
+/// mention the extension.
+const MIME_TYPES: &[(&str, &str)] = &[
+    // Text
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    // Images
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("ico", "image/x-icon"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    // Audio and video
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    // Others
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+];
+
+/// Get the media type of the file at `path`. Extensions are matched
+/// without regard to case, the configured `mime_types` first and then the
+/// built in table. Anything else is `default_mime_type`.
+pub fn content_type(path: &Path, config: &Config) -> String {
+    let ext = match path.extension().and_then(|x| x.to_str()) {
+        Some(ext) => ext.to_ascii_lowercase(),
+        None => return default_type(config),
+    };
+
+    if let Some(types) = &config.mime_types {
+        for (key, value) in types {
+            if key.to_ascii_lowercase() == ext {
+                return value.to_string();
+            }
+        }
+    }
+
+    match MIME_TYPES.iter().find(|(key, _)| *key == ext) {
+        Some((_, value)) => value.to_string(),
+        None => default_type(config),
+    }
+}
+
+fn default_type(config: &Config) -> String {
+    match &config.default_mime_type {
+        Some(x) => x.to_string(),
+        None => DEFAULT_MIME_TYPE.to_string(),
+    }
+}
@@ -0,0 +1,678 @@
+//! Multipart Form Data
+//!
+//! Parser for `multipart/form-data` bodies, the way HTML forms upload
+//! files. See RFC 7578 for more information. The body is parsed as it is
+//! read from the connection and a part larger than `upload_in_memory` is
+//! written to a temporary file instead of being kept in memory.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::configuration::{Config, DEFAULT_UPLOAD_IN_MEMORY, DEFAULT_UPLOAD_MAX_PART};
+use crate::protocol::StatusCode;
+use crate::request::{ParsingError, ReadError};
+
+const READ_CHUNK: usize = 8192;
+
+/// Largest block of header fields a part may start with.
+const MAX_PART_HEADER: usize = 8192;
+
+/// Longest boundary allowed by RFC 2046.
+const MAX_BOUNDARY: usize = 70;
+
+// Keeps the names of temporary files apart within the process.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// A body that does not follow the format, reported where it was found.
+macro_rules! invalid {
+    ($message:expr) => {
+        ReadError::Invalid(ParsingError {
+            message: $message.to_string(),
+            status: StatusCode::BadRequest,
+            line: line!(),
+            column: column!(),
+        })
+    };
+}
+
+// A part could not be written to its temporary file, nothing is wrong
+// with the request.
+macro_rules! spool_error {
+    ($e:expr) => {
+        ReadError::Invalid(ParsingError {
+            message: format!("Could not store an uploaded part! {}", $e),
+            status: StatusCode::InternalServerError,
+            line: line!(),
+            column: column!(),
+        })
+    };
+}
+
+/// The parts of a `multipart/form-data` body, in the order they were sent.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    parts: Vec<Part>,
+}
+
+/// A single field of a form, or a file uploaded with it.
+#[derive(Debug)]
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: Data,
+}
+
+// Where the content of a part is kept.
+#[derive(Debug)]
+enum Data {
+    Memory(Vec<u8>),
+    Spooled { file: TempFile, len: u64 },
+}
+
+// A file that is removed once the request is done with it.
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Multipart {
+    /// Every part of the body.
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    /// The first part named `name`.
+    pub fn get(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|x| x.name == name)
+    }
+}
+
+impl Part {
+    /// The name of the form field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the uploaded file as the client gave it. It must not be
+    /// trusted as a path.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// The media type of the part, if the client sent one.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Size of the content in bytes.
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            Data::Memory(bytes) => bytes.len() as u64,
+            Data::Spooled { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The content, when it was small enough to be kept in memory.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            Data::Memory(bytes) => Some(bytes),
+            Data::Spooled { .. } => None,
+        }
+    }
+
+    /// The temporary file holding the content of a large part. It is
+    /// removed once the request has been answered.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            Data::Memory(_) => None,
+            Data::Spooled { file, .. } => Some(&file.path),
+        }
+    }
+
+    /// Read the content, wherever it is kept.
+    pub fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            Data::Memory(bytes) => Ok(Box::new(&bytes[..])),
+            Data::Spooled { file, .. } => Ok(Box::new(File::open(&file.path)?)),
+        }
+    }
+
+    /// Copy the content to the file at `path`. Returns the number of bytes
+    /// copied.
+    pub fn save_as<P: AsRef<Path>>(&self, path: P) -> io::Result<u64> {
+        let mut to = File::create(path)?;
+        io::copy(&mut self.open()?, &mut to)
+    }
+}
+
+/// Get the `boundary` parameter of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.splitn(2, ';');
+    params.next()?;
+    let boundary = parameter(params.next()?, "boundary")?;
+
+    if boundary.is_empty() || boundary.len() > MAX_BOUNDARY {
+        return None;
+    }
+    Some(boundary)
+}
+
+/// Parse a `multipart/form-data` body separated by `boundary`. `body` must
+/// end where the Entity-Body ends. Parts larger than `upload_max_part` are
+/// a 413 Request Entity Too Large, a body that is cut short or does not
+/// follow the format is a 400 Bad Request.
+pub fn parse<R: Read>(
+    body: &mut R,
+    boundary: &str,
+    config: &Config,
+) -> Result<Multipart, ReadError> {
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // The first delimiter may start the body without a line break before it
+    let mut reader = PartReader {
+        body,
+        buf: b"\r\n".to_vec(),
+    };
+    let mut multipart = Multipart::default();
+
+    // Skip the preamble
+    reader.copy_until(&delimiter, &mut io::sink(), 0)?;
+
+    loop {
+        // A delimiter followed by `--` ends the body, one followed by a line
+        // break starts the next part.
+        reader.fill_to(2)?;
+        if reader.buf.starts_with(b"--") {
+            break;
+        }
+        if !reader.buf.starts_with(b"\r\n") {
+            return Err(invalid!("Malformed multipart boundary line"));
+        }
+
+        let mut part = reader.read_part_header()?;
+        let mut writer = PartWriter::new(config);
+        let max = writer.max;
+        reader.copy_until(&delimiter, &mut writer, max)?;
+        part.data = writer.finish()?;
+        multipart.parts.push(part);
+    }
+
+    // Skip the epilogue
+    if let Err(e) = io::copy(reader.body, &mut io::sink()) {
+        return Err(ReadError::Io(e));
+    }
+
+    Ok(multipart)
+}
+
+// The body being parsed and whatever was read from it but not used yet.
+struct PartReader<'a, R> {
+    body: &'a mut R,
+    buf: Vec<u8>,
+}
+
+impl<'a, R: Read> PartReader<'a, R> {
+    // Read more of the body. Running out of body before the final delimiter
+    // means the request was cut short.
+    fn fill(&mut self) -> Result<(), ReadError> {
+        let start = self.buf.len();
+        self.buf.resize(start + READ_CHUNK, 0);
+
+        loop {
+            match self.body.read(&mut self.buf[start..]) {
+                Ok(0) => {
+                    self.buf.truncate(start);
+                    return Err(invalid!("Multipart body ends before its final boundary"));
+                }
+                Ok(size) => {
+                    self.buf.truncate(start + size);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(ReadError::Io(e));
+                }
+            }
+        }
+    }
+
+    fn fill_to(&mut self, len: usize) -> Result<(), ReadError> {
+        while self.buf.len() < len {
+            self.fill()?;
+        }
+        Ok(())
+    }
+
+    // Write everything up to `delimiter` to `to` and consume the delimiter.
+    // A delimiter can be split between two reads, so the last bytes that
+    // could be the start of one are held back until more is read.
+    fn copy_until<W: Write>(
+        &mut self,
+        delimiter: &[u8],
+        to: &mut W,
+        max: u64,
+    ) -> Result<(), ReadError> {
+        let mut written = 0;
+        let mut write = |bytes: &[u8]| -> Result<(), ReadError> {
+            written += bytes.len() as u64;
+            if max > 0 && written > max {
+                return Err(ReadError::Invalid(ParsingError {
+                    message: format!("Multipart part is larger than {} bytes", max),
+                    status: StatusCode::RequestEntityTooLarge,
+                    line: line!(),
+                    column: column!(),
+                }));
+            }
+            to.write_all(bytes).map_err(|e| spool_error!(e))
+        };
+
+        loop {
+            if let Some(i) = find(&self.buf, delimiter) {
+                write(&self.buf[..i])?;
+                self.buf.drain(..i + delimiter.len());
+                return Ok(());
+            }
+
+            let keep = delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let n = self.buf.len() - keep;
+                write(&self.buf[..n])?;
+                self.buf.drain(..n);
+            }
+            self.fill()?;
+        }
+    }
+
+    // Read the header fields of a part, the buffer starts with the line
+    // break ending its delimiter.
+    fn read_part_header(&mut self) -> Result<Part, ReadError> {
+        let end = loop {
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buf.len() > MAX_PART_HEADER {
+                return Err(invalid!("Multipart part header is too large"));
+            }
+            self.fill()?;
+        };
+
+        let head = match std::str::from_utf8(&self.buf[2..end.max(2)]) {
+            Ok(head) => head.to_string(),
+            Err(_) => return Err(invalid!("Multipart part header is not UTF-8")),
+        };
+        self.buf.drain(..end + 4);
+
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in head.split("\r\n").filter(|x| !x.is_empty()) {
+            let (name, value) = match line.find(':') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => return Err(invalid!("Malformed multipart part header")),
+            };
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.to_string());
+            }
+        }
+
+        // Every part of a form must say which field it belongs to
+        let disposition = match disposition {
+            Some(x) => x,
+            None => return Err(invalid!("Multipart part without Content-Disposition")),
+        };
+        let mut params = disposition.splitn(2, ';');
+        let kind = params.next().unwrap_or("").trim();
+        let params = params.next().unwrap_or("");
+        let name = match parameter(params, "name") {
+            Some(name) if kind.eq_ignore_ascii_case("form-data") => name,
+            _ => return Err(invalid!("Multipart part is not named form-data")),
+        };
+
+        Ok(Part {
+            name,
+            filename: parameter(params, "filename"),
+            content_type,
+            data: Data::Memory(Vec::new()),
+        })
+    }
+}
+
+// Takes the content of a part, in memory until it grows past
+// `upload_in_memory` and in a temporary file after that.
+struct PartWriter<'a> {
+    config: &'a Config,
+    max: u64,
+    memory: Vec<u8>,
+    spool: Option<(TempFile, File)>,
+    len: u64,
+}
+
+impl<'a> PartWriter<'a> {
+    fn new(config: &'a Config) -> Self {
+        PartWriter {
+            config,
+            max: config.upload_max_part.unwrap_or(DEFAULT_UPLOAD_MAX_PART),
+            memory: Vec::new(),
+            spool: None,
+            len: 0,
+        }
+    }
+
+    fn finish(mut self) -> Result<Data, ReadError> {
+        match self.spool.take() {
+            Some((file, mut handle)) => {
+                handle.flush().map_err(|e| spool_error!(e))?;
+                Ok(Data::Spooled {
+                    file,
+                    len: self.len,
+                })
+            }
+            None => Ok(Data::Memory(std::mem::take(&mut self.memory))),
+        }
+    }
+}
+
+impl<'a> Write for PartWriter<'a> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let in_memory = self
+            .config
+            .upload_in_memory
+            .unwrap_or(DEFAULT_UPLOAD_IN_MEMORY);
+
+        if self.spool.is_none() && self.memory.len() + bytes.len() > in_memory {
+            let (file, mut handle) = temp_file(self.config)?;
+            handle.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.spool = Some((file, handle));
+        }
+
+        match &mut self.spool {
+            Some((_, handle)) => handle.write_all(bytes)?,
+            None => self.memory.extend_from_slice(bytes),
+        }
+        self.len += bytes.len() as u64;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.spool {
+            Some((_, handle)) => handle.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Create a new temporary file in `upload_dir`, or the system's temporary
+// directory. Only the server's user may read it.
+fn temp_file(config: &Config) -> io::Result<(TempFile, File)> {
+    let dir = match &config.upload_dir {
+        Some(x) => PathBuf::from(x),
+        None => env::temp_dir(),
+    };
+
+    loop {
+        let count = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("tiny_http_{}_{}.upload", process::id(), count));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        match options.open(&path) {
+            Ok(handle) => return Ok((TempFile { path }, handle)),
+            // Left behind by an earlier process with the same id
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Get the value of the parameter `name` from `; `-separated parameters,
+// unquoting it if needed.
+fn parameter(params: &str, name: &str) -> Option<String> {
+    let mut rest = params;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if rest.is_empty() {
+            return None;
+        }
+
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        rest = &rest[eq + 1..];
+
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            // Up to the closing quote, `\` escapes the next character
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((_, '\\')) => {
+                        if let Some((_, c)) = chars.next() {
+                            value.push(c);
+                        }
+                    }
+                    Some((i, '"')) => break i + 1,
+                    Some((_, c)) => value.push(c),
+                    None => break quoted.len(),
+                }
+            };
+            rest = &quoted[end..];
+            value
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+            value
+        };
+
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+    }
+}
+
+// Position of the first `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----tiny_http";
+
+    // Hands out `data` a few bytes per read, like a slow client.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(out.len()).min(self.data.len());
+            out[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    // A body with a field and a file, the file holding `content`.
+    fn body(content: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            concat!(
+                "preamble\r\n",
+                "--{b}\r\n",
+                "Content-Disposition: form-data; name=\"title\"\r\n",
+                "\r\n",
+                "Hello, world\r\n",
+                "--{b}\r\n",
+                "Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+            ),
+            b = BOUNDARY
+        )
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(format!("\r\n--{}--\r\nepilogue", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn parse_with(body: &[u8], step: usize, config: &Config) -> Result<Multipart, ReadError> {
+        parse(&mut Trickle { data: body, step }, BOUNDARY, config)
+    }
+
+    fn status(result: Result<Multipart, ReadError>) -> StatusCode {
+        match result {
+            Err(ReadError::Invalid(e)) => e.status,
+            Err(ReadError::Io(e)) => panic!("expected an invalid body, got {}", e),
+            Err(ReadError::Closed) => panic!("expected an invalid body, got Closed"),
+            Ok(_) => panic!("expected an invalid body"),
+        }
+    }
+
+    // A directory of its own for the files a test spools.
+    fn upload_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tiny_http_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn boundary_split_across_reads() {
+        // Content that almost looks like a delimiter
+        let content = format!("line\r\n--{}x\r\n-", &BOUNDARY[..BOUNDARY.len() - 1]);
+        let body = body(content.as_bytes());
+        for step in 1..=9 {
+            let multipart = parse_with(&body, step, &Config::default()).unwrap();
+            assert_eq!(multipart.parts().len(), 2, "step {}", step);
+
+            let title = multipart.get("title").unwrap();
+            assert_eq!(title.bytes(), Some(&b"Hello, world"[..]));
+            assert_eq!(title.filename(), None);
+
+            let upload = multipart.get("upload").unwrap();
+            assert_eq!(upload.filename(), Some("a \"b\".txt"));
+            assert_eq!(upload.content_type(), Some("text/plain"));
+            assert_eq!(upload.bytes(), Some(content.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn large_part_is_spooled() {
+        let dir = upload_dir("spool");
+        let config = Config {
+            upload_dir: Some(dir.to_string_lossy().into_owned()),
+            upload_in_memory: Some(16),
+            ..Config::default()
+        };
+        let content: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+
+        let multipart = parse_with(&body(&content), 7, &config).unwrap();
+        assert!(multipart.get("title").unwrap().path().is_none());
+
+        let upload = multipart.get("upload").unwrap();
+        assert_eq!(upload.len(), content.len() as u64);
+        assert_eq!(upload.bytes(), None);
+        let path = upload.path().unwrap().to_path_buf();
+        assert!(path.starts_with(&dir));
+        assert_eq!(fs::read(&path).unwrap(), content);
+
+        // Removed with the request
+        drop(multipart);
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn part_too_large() {
+        let dir = upload_dir("max_part");
+        let config = Config {
+            upload_dir: Some(dir.to_string_lossy().into_owned()),
+            upload_in_memory: Some(16),
+            upload_max_part: Some(100),
+            ..Config::default()
+        };
+
+        assert!(parse_with(&body(&[b'x'; 100]), 3, &config).is_ok());
+        assert_eq!(
+            status(parse_with(&body(&[b'x'; 101]), 3, &config)),
+            StatusCode::RequestEntityTooLarge
+        );
+        // Nothing is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn malformed_bodies() {
+        let config = Config::default();
+        let full = body(b"content");
+
+        // Cut short before the final delimiter
+        let cut = &full[..full.len() - 20];
+        assert_eq!(status(parse_with(cut, 5, &config)), StatusCode::BadRequest);
+
+        for body in &[
+            format!("--{}\r\nname: x\r\n\r\nvalue\r\n--{}--", BOUNDARY, BOUNDARY),
+            format!(
+                "--{}\r\nContent-Disposition: attachment; name=\"x\"\r\n\r\nv\r\n--{}--",
+                BOUNDARY, BOUNDARY
+            ),
+            format!("--{}\r\nno colon\r\n\r\nv\r\n--{}--", BOUNDARY, BOUNDARY),
+            format!("--{}garbage", BOUNDARY),
+            "no delimiter at all".to_string(),
+        ] {
+            assert_eq!(
+                status(parse_with(body.as_bytes(), 5, &config)),
+                StatusCode::BadRequest,
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn boundary_parameter() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; BOUNDARY=\"a b;c\"").as_deref(),
+            Some("a b;c")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        let long = format!("multipart/form-data; boundary={}", "x".repeat(71));
+        assert_eq!(boundary(&long), None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Worker Thread Pool
+//!
+//! A fixed number of worker threads serve accepted connections. Connections
+//! wait for a free worker in a bounded queue, when the queue is full the
+//! connection is handed back to the caller to be turned away. What a
+//! connection is, is up to the caller.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+type Handler<T> = Arc<dyn Fn(T) + Send + Sync>;
+
+/// Runs a connection handler on a fixed set of threads.
+pub struct ThreadPool<T: Send + 'static> {
+    workers: Vec<Worker>,
+    sender: Option<SyncSender<T>>,
+    /// Number of worker threads still running
+    alive: Arc<(Mutex<usize>, Condvar)>,
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    /// Create a pool of `size` workers, each running `handler` for the
+    /// connections it receives. At most `queue` connections wait for a
+    /// free worker. A `size` of 0 is treated as 1.
+    pub fn new<F>(size: usize, queue: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let handler: Handler<T> = Arc::new(handler);
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let alive = Arc::new((Mutex::new(size.max(1)), Condvar::new()));
+
+        let workers = (0..size.max(1))
+            .map(|id| {
+                Worker::new(
+                    id,
+                    Arc::clone(&receiver),
+                    Arc::clone(&alive),
+                    Arc::clone(&handler),
+                )
+            })
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+            alive,
+        }
+    }
+
+    /// Stop taking connections and give the workers up to `timeout` to
+    /// finish the connections they are serving and the ones still queued.
+    /// Workers that are not done in time are left running on their own.
+    /// Returns the number of workers that were abandoned.
+    pub fn shutdown(&mut self, timeout: Duration) -> usize {
+        drop(self.sender.take());
+
+        let deadline = Instant::now() + timeout;
+        let (lock, done) = &*self.alive;
+        let mut alive = match lock.lock() {
+            Ok(alive) => alive,
+            Err(_) => return self.workers.len(),
+        };
+
+        while *alive > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            alive = match done.wait_timeout(alive, deadline - now) {
+                Ok((alive, _)) => alive,
+                Err(_) => return self.workers.len(),
+            };
+        }
+
+        let abandoned = *alive;
+        drop(alive);
+
+        if abandoned == 0 {
+            self.join();
+        } else {
+            // Dropping the handles detaches the threads
+            self.workers.clear();
+        }
+
+        abandoned
+    }
+
+    fn join(&mut self) {
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    error!("Worker {} panicked", worker.id);
+                }
+            }
+        }
+    }
+
+    /// Queue `conn` for the next free worker. If the queue is full the
+    /// connection is given back so the caller can respond to it.
+    pub fn dispatch(&self, conn: T) -> Result<(), T> {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return Err(conn),
+        };
+
+        match sender.try_send(conn) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(conn)) => Err(conn),
+            Err(TrySendError::Disconnected(conn)) => Err(conn),
+        }
+    }
+}
+
+// Closing the queue lets every worker finish what it has and exit.
+impl<T: Send + 'static> Drop for ThreadPool<T> {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        self.join();
+    }
+}
+
+// Counts a worker out when its thread ends, even by panicking.
+struct AliveGuard(Arc<(Mutex<usize>, Condvar)>);
+
+impl Drop for AliveGuard {
+    fn drop(&mut self) {
+        let (lock, done) = &*self.0;
+        if let Ok(mut alive) = lock.lock() {
+            *alive -= 1;
+            done.notify_all();
+        }
+    }
+}
+
+impl Worker {
+    fn new<T: Send + 'static>(
+        id: usize,
+        receiver: Arc<Mutex<Receiver<T>>>,
+        alive: Arc<(Mutex<usize>, Condvar)>,
+        handler: Handler<T>,
+    ) -> Self {
+        let thread = thread::spawn(move || {
+            let _guard = AliveGuard(alive);
+
+            loop {
+                // The lock is released before the connection is handled
+                let conn = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break,
+                };
+
+                match conn {
+                    Ok(conn) => {
+                        // A panic while handling one connection must not take
+                        // the worker down with it.
+                        if panic::catch_unwind(AssertUnwindSafe(|| handler(conn))).is_err() {
+                            error!("Worker {} lost a connection to a panic", id);
+                        }
+                    }
+                    // The pool was shut down
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Worker {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
@@ -79,6 +79,29 @@ pub enum RequestVersion {
 ///     WWW-Authenticate: Used with 401 Unauthorized response message. The
 ///             field consists of at least one challenge that indicates the
 ///             authentication scheme.
+///     Retry-After: Used with 503 Service Unavailable to tell the client
+///             how many seconds to wait before trying again.
+///     Connection: Whether the connection stays open after the response,
+///             `keep-alive` or `close`.
+///     Keep-Alive: How long, and for how many requests, a persistent
+///             connection stays open.
+///     ETag: An opaque validator of the resource, it changes whenever the
+///             resource does.
+///     If-Match: Only carry out the request if the resource has one of the
+///             listed ETags, otherwise 412 Precondition Failed.
+///     If-None-Match: Only carry out the request if the resource has none
+///             of the listed ETags, otherwise 304 Not Modified for GET and
+///             HEAD and 412 Precondition Failed for other methods.
+///     If-Unmodified-Since: Only carry out the request if the resource has
+///             not been modified since the date, otherwise 412.
+///     Range: The byte ranges of the resource the client wants, answered
+///             with 206 Partial Content.
+///     If-Range: Only honor `Range` if the resource still has this ETag
+///             or Last-Modified date, otherwise send all of it.
+///     Accept-Ranges: Tells the client it may ask for byte ranges.
+///     Content-Range: Where in the resource a partial body belongs.
+///     Host: The host and port of the resource, picks the virtual host.
+///             Required in every HTTP/1.1 request.
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum RequestField {
     /// HTTP/1.0
@@ -99,7 +122,19 @@ pub enum RequestField {
     UserAgent,
     WwwAuthenticate,
     // extended HTTP/1.0
+    RetryAfter,
+    KeepAlive,
     // exclusive HTTP/1.1
+    Connection,
+    ETag,
+    IfMatch,
+    IfNoneMatch,
+    IfUnmodifiedSince,
+    Range,
+    IfRange,
+    AcceptRanges,
+    ContentRange,
+    Host,
     // others
     Unknown,
 }
@@ -112,13 +147,21 @@ pub enum StatusCode {
     Created = 201,
     Accepted = 202,
     NoContent = 204,
+    PartialContent = 206,
     MovedPermanently = 301,
     MovedTemporarily = 302,
+    SeeOther = 303,
     NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
     BadRequest = 400,
     Unauthorized = 401,
     Forbidden = 403,
     NotFound = 404,
+    MethodNotAllowed = 405,
+    PreconditionFailed = 412,
+    RequestEntityTooLarge = 413,
+    RangeNotSatisfiable = 416,
     InternalServerError = 500,
     NotImplemented = 501,
     BadGateway = 502,
@@ -146,13 +189,21 @@ pub fn status_to_string(s: &StatusCode) -> String {
         StatusCode::Created => "201 Created".to_string(),
         StatusCode::Accepted => "202 Accepted".to_string(),
         StatusCode::NoContent => "204 No Content".to_string(),
+        StatusCode::PartialContent => "206 Partial Content".to_string(),
         StatusCode::MovedPermanently => "301 Moved Permanently".to_string(),
-        StatusCode::MovedTemporarily => "202 Moved Temporarily".to_string(),
+        StatusCode::MovedTemporarily => "302 Moved Temporarily".to_string(),
+        StatusCode::SeeOther => "303 See Other".to_string(),
         StatusCode::NotModified => "304 Not Modified".to_string(),
+        StatusCode::TemporaryRedirect => "307 Temporary Redirect".to_string(),
+        StatusCode::PermanentRedirect => "308 Permanent Redirect".to_string(),
         StatusCode::BadRequest => "400 Bad Request".to_string(),
         StatusCode::Unauthorized => "401 Unauthorized".to_string(),
         StatusCode::Forbidden => "403 Forbidden".to_string(),
         StatusCode::NotFound => "404 Not Found".to_string(),
+        StatusCode::MethodNotAllowed => "405 Method Not Allowed".to_string(),
+        StatusCode::PreconditionFailed => "412 Precondition Failed".to_string(),
+        StatusCode::RequestEntityTooLarge => "413 Request Entity Too Large".to_string(),
+        StatusCode::RangeNotSatisfiable => "416 Range Not Satisfiable".to_string(),
         StatusCode::InternalServerError => "500 Internal Server Error".to_string(),
         StatusCode::NotImplemented => "501 Not Implemented".to_string(),
         StatusCode::BadGateway => "502 Bad Gateway".to_string(),
@@ -161,6 +212,11 @@ pub fn status_to_string(s: &StatusCode) -> String {
     }
 }
 
+/// Is the status a client error (4xx) or a server error (5xx)?
+pub fn status_is_error(s: &StatusCode) -> bool {
+    *s != StatusCode::Unknown && *s as u16 >= 400
+}
+
 /// Get the string representation of a HTTP version
 pub fn version_to_string(r: &RequestVersion) -> String {
     match r {
@@ -190,6 +246,18 @@ pub fn field_to_string(r: &RequestField) -> String {
         RequestField::Server => "Server: ".to_string(),
         RequestField::UserAgent => "User-Agent: ".to_string(),
         RequestField::WwwAuthenticate => "WWW-Authenticate: ".to_string(),
+        RequestField::RetryAfter => "Retry-After: ".to_string(),
+        RequestField::KeepAlive => "Keep-Alive: ".to_string(),
+        RequestField::Connection => "Connection: ".to_string(),
+        RequestField::ETag => "ETag: ".to_string(),
+        RequestField::IfMatch => "If-Match: ".to_string(),
+        RequestField::IfNoneMatch => "If-None-Match: ".to_string(),
+        RequestField::IfUnmodifiedSince => "If-Unmodified-Since: ".to_string(),
+        RequestField::Range => "Range: ".to_string(),
+        RequestField::IfRange => "If-Range: ".to_string(),
+        RequestField::AcceptRanges => "Accept-Ranges: ".to_string(),
+        RequestField::ContentRange => "Content-Range: ".to_string(),
+        RequestField::Host => "Host: ".to_string(),
         RequestField::Unknown => "Unknown: ".to_string(),
     }
 }
@@ -0,0 +1,178 @@
+//! Byte Ranges
+//!
+//! Parses the `Range` request field so a client can ask for part of a
+//! resource, for example to resume a download. See RFC 7233 for more
+//! information.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+/// Most ranges served from one request. A client asking for more gets the
+/// whole resource instead.
+const MAX_RANGES: usize = 64;
+
+/// What a `Range` field asks for out of a resource.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The field could not be parsed, or names a unit other than bytes.
+    /// It is ignored and the whole resource is sent.
+    Full,
+    /// None of the ranges overlap the resource.
+    Unsatisfiable,
+    /// The satisfiable ranges, inclusive first and last byte positions.
+    Partial(Vec<(u64, u64)>),
+}
+
+/// Parse `value`, a `Range` field such as `bytes=0-499,-500`, against a
+/// resource of `len` bytes. Ranges that start past the end are dropped and
+/// ranges that run past the end are cut short. Ranges that overlap or touch
+/// are merged, so no byte is sent twice, and the rest are in order.
+pub fn parse_range(value: &str, len: u64) -> Ranges {
+    let value = value.trim();
+    if !value
+        .get(..6)
+        .is_some_and(|x| x.eq_ignore_ascii_case("bytes="))
+    {
+        return Ranges::Full;
+    }
+
+    let specs: Vec<&str> = value[6..]
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+
+    let mut ranges = Vec::<(u64, u64)>::new();
+    for spec in specs {
+        let (first, last) = match spec.find('-') {
+            Some(i) => (spec[..i].trim(), spec[i + 1..].trim()),
+            None => return Ranges::Full,
+        };
+
+        if first.is_empty() {
+            // The final `last` bytes
+            let suffix = match last.parse::<u64>() {
+                Ok(suffix) => suffix,
+                Err(_) => return Ranges::Full,
+            };
+            if suffix > 0 && len > 0 {
+                ranges.push((len.saturating_sub(suffix), len - 1));
+            }
+            continue;
+        }
+
+        let first = match first.parse::<u64>() {
+            Ok(first) => first,
+            Err(_) => return Ranges::Full,
+        };
+        let last = if last.is_empty() {
+            u64::MAX
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= first => last,
+                _ => return Ranges::Full,
+            }
+        };
+
+        if first < len {
+            ranges.push((first, last.min(len - 1)));
+        }
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_unstable();
+    let mut merged = Vec::<(u64, u64)>::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+
+    Ranges::Partial(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-99", 1000),
+            Ranges::Partial(vec![(0, 99)])
+        );
+        assert_eq!(
+            parse_range("BYTES=10-10", 1000),
+            Ranges::Partial(vec![(10, 10)])
+        );
+        assert_eq!(parse_range(" bytes = 0-1", 1000), Ranges::Full);
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            Ranges::Partial(vec![(900, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=-5000", 1000),
+            Ranges::Partial(vec![(0, 999)])
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-1", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            Ranges::Partial(vec![(500, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-5000", 1000),
+            Ranges::Partial(vec![(0, 999)])
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn overlapping_ranges_are_merged() {
+        let many = format!("bytes={}", vec!["0-"; MAX_RANGES].join(","));
+        assert_eq!(parse_range(&many, 1000), Ranges::Partial(vec![(0, 999)]));
+        assert_eq!(
+            parse_range("bytes=500-599,0-99,50-149", 1000),
+            Ranges::Partial(vec![(0, 149), (500, 599)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-9,10-19,-10", 1000),
+            Ranges::Partial(vec![(0, 19), (990, 999)])
+        );
+    }
+
+    #[test]
+    fn malformed_ranges() {
+        assert_eq!(parse_range("", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=5", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=9-5", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), Ranges::Full);
+        assert_eq!(parse_range("items=0-1", 1000), Ranges::Full);
+        let many = format!("bytes={}", vec!["0-1"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse_range(&many, 1000), Ranges::Full);
+    }
+
+    #[test]
+    fn non_ascii_ranges() {
+        assert_eq!(parse_range("bytesé=0-1", 1000), Ranges::Full);
+        assert_eq!(parse_range("éé", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=0-1é", 1000), Ranges::Full);
+    }
+}
@@ -10,10 +10,23 @@
 //!
 
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
 use std::str;
 
+use crate::configuration::{Config, DEFAULT_MAX_BUFFER, DEFAULT_UPLOAD_MAX_TOTAL};
+use crate::multipart::{self, Multipart};
 use crate::protocol;
 
+/// Number of bytes requested from the stream on each read while the
+/// request line and header fields are collected.
+const READ_CHUNK: usize = 1024;
+
+/// Media type of a body of HTML form fields.
+const FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+/// Media type of a body of HTML form fields and uploaded files.
+const FORM_DATA: &str = "multipart/form-data";
+
 /// The standard error that the request parser will produce if there
 /// is any problem parsing the request. For the most part, if the
 /// request line is bad, then the entire request is bad. A basic
@@ -23,10 +36,37 @@ use crate::protocol;
 #[derive(Debug, Clone)]
 pub struct ParsingError {
     pub message: String,
+    /// The status the client should be answered with
+    pub status: protocol::StatusCode,
     pub line: u32,
     pub column: u32,
 }
 
+/// The body of a POST request, see `Header::post_data`.
+#[derive(Debug)]
+pub enum PostData<'a> {
+    /// No body was sent
+    Empty,
+    /// Fields of an `application/x-www-form-urlencoded` body, decoded
+    Form(&'a HashMap<String, String>),
+    /// Fields and files of a `multipart/form-data` body
+    Multipart(&'a Multipart),
+    /// Any other body, exactly as it was sent
+    Raw(&'a [u8]),
+}
+
+/// Reasons `read_request` could not produce a `Header`.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client closed the connection before sending anything.
+    Closed,
+    /// Reading from the stream failed, there is nobody to respond to.
+    Io(io::Error),
+    /// The request broke a limit or could not be framed. The client
+    /// should be answered with the error's status.
+    Invalid(ParsingError),
+}
+
 /// When a request is initiated, the contents of
 /// that request are stored here.
 ///
@@ -47,16 +87,32 @@ pub struct Header {
     method: protocol::RequestMethod,
     /// Request version e.g SimpleRequest, HTTP/1.0, HTTP/1.1
     version: protocol::RequestVersion,
-    /// URI
+    /// The request line as it was sent
+    request_line: String,
+    /// URI path, percent-decoded and without dot-segments
     path: String,
+    /// Query of the URI, everything after `?`, still percent-encoded
+    query: String,
+    /// Host the request is for, lowercase and without the port
+    host: Option<String>,
+    /// User who logged in with HTTP Basic authentication
+    user: Option<String>,
     /// HTTP/1.0 Known fields
     fields: HashMap<protocol::RequestField, String>,
     /// Possible fields from HTTP/1.1 request, non-documented fileds.
     /// See [RFC 1945, Section 10. Header Field Definitions]
     unknown_fields: HashMap<String, String>,
-    /// POST fields. It should only be used with a POST request
-    /// See [RFC 1945 Secion 8.3 POST]
+    /// Percent-decoded fields of an `application/x-www-form-urlencoded`
+    /// POST body. See [RFC 1945 Secion 8.3 POST]
     post_fields: HashMap<String, String>,
+    /// Entity-Body, exactly `Content-Length` bytes long.
+    /// See [RFC 1945 Section 7.2 Entity Body]
+    body: Vec<u8>,
+    /// Parts of a `multipart/form-data` POST body, which is parsed as it
+    /// is read and never stored in `body`.
+    multipart: Option<Multipart>,
+    /// Parameters taken from the path by the route the request matched
+    params: HashMap<String, String>,
 }
 
 // Create a empty header
@@ -66,18 +122,393 @@ impl Default for Header {
             valid: false,
             method: protocol::RequestMethod::Unknown,
             version: protocol::RequestVersion::Unknown,
+            request_line: String::new(),
             path: String::new(),
+            query: String::new(),
+            host: None,
+            user: None,
             fields: HashMap::new(),
             unknown_fields: HashMap::new(),
             post_fields: HashMap::new(),
+            body: Vec::new(),
+            multipart: None,
+            params: HashMap::new(),
+        }
+    }
+}
+
+/// Read a complete request from `conn`.
+///
+/// The stream is read until the end of the header fields (`CRLF CRLF`, or
+/// the end of the request line for a simple request), then exactly
+/// `Content-Length` bytes of Entity-Body are read. `max_buffer` is a hard
+/// limit on the size of the whole request: header fields larger than it
+/// are a 400 Bad Request, a body that would not fit is a 413 Request
+/// Entity Too Large. A `multipart/form-data` POST body is parsed as it is
+/// read instead, limited by `upload_max_total`.
+///
+/// `buf` holds bytes already read from the connection. On success it is
+/// left with whatever the client sent after this request, the start of
+/// the next request on a persistent connection.
+pub fn read_request<R: Read>(
+    conn: &mut R,
+    buf: &mut Vec<u8>,
+    config: &Config,
+) -> Result<Header, ReadError> {
+    let max_buffer = config.max_buffer.unwrap_or(DEFAULT_MAX_BUFFER);
+    let mut chunk = [0_u8; READ_CHUNK];
+
+    let mut scanned = 0;
+    let mut line_end = None;
+    let head_len = loop {
+        if let Some(end) = Header::find_head_end(buf, scanned, &mut line_end) {
+            break end;
+        }
+        scanned = buf.len();
+
+        if buf.len() >= max_buffer {
+            return Err(ReadError::Invalid(ParsingError {
+                message: format!("Request header is larger than {} bytes", max_buffer),
+                status: protocol::StatusCode::BadRequest,
+                line: line!(),
+                column: column!(),
+            }));
+        }
+
+        let size = match conn.read(&mut chunk) {
+            Ok(size) => size,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // An idle connection timing out is the same as the client
+            // closing it
+            Err(e)
+                if buf.is_empty()
+                    && (e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut) =>
+            {
+                return Err(ReadError::Closed)
+            }
+            Err(e) => return Err(ReadError::Io(e)),
+        };
+
+        if size == 0 {
+            if buf.is_empty() {
+                return Err(ReadError::Closed);
+            }
+            return Err(ReadError::Invalid(ParsingError {
+                message: "Connection closed before the end of the header".to_string(),
+                status: protocol::StatusCode::BadRequest,
+                line: line!(),
+                column: column!(),
+            }));
+        }
+
+        buf.extend_from_slice(&chunk[..size]);
+    };
+
+    // The last read may of carried us past the limit
+    if head_len > max_buffer {
+        return Err(ReadError::Invalid(ParsingError {
+            message: format!("Request header is larger than {} bytes", max_buffer),
+            status: protocol::StatusCode::BadRequest,
+            line: line!(),
+            column: column!(),
+        }));
+    }
+
+    let mut header = Header::new(&buf[..head_len]);
+    if !header.valid {
+        // The response will turn this into a 400 Bad Request. Nothing after
+        // it can be trusted so the connection is not kept alive.
+        buf.clear();
+        return Ok(header);
+    }
+
+    // Only bodies framed by Content-Length are read. A chunked body left
+    // unread would be taken for the next request.
+    if header.get_unknown_field("Transfer-Encoding").is_some() {
+        return Err(ReadError::Invalid(ParsingError {
+            message: "Transfer-Encoding is not supported".to_string(),
+            status: protocol::StatusCode::NotImplemented,
+            line: line!(),
+            column: column!(),
+        }));
+    }
+
+    let length = match header.fields.get(&protocol::RequestField::ContentLength) {
+        Some(x) => match content_length(x) {
+            Some(length) => length,
+            None => {
+                return Err(ReadError::Invalid(ParsingError {
+                    message: format!("Invalid Content-Length: {}", x),
+                    status: protocol::StatusCode::BadRequest,
+                    line: line!(),
+                    column: column!(),
+                }))
+            }
+        },
+        None => 0,
+    };
+
+    if header.method == protocol::RequestMethod::Post
+        && header.media_type().as_deref() == Some(FORM_DATA)
+    {
+        read_multipart(conn, buf, head_len, length, &mut header, config)?;
+        return Ok(header);
+    }
+
+    // `length` is whatever the client sent, it is only a `usize` once it is
+    // known to fit in the buffer
+    if length > max_buffer.saturating_sub(head_len) as u64 {
+        return Err(ReadError::Invalid(ParsingError {
+            message: format!(
+                "Entity-Body of {} bytes is larger than {} bytes",
+                length,
+                max_buffer.saturating_sub(head_len)
+            ),
+            status: protocol::StatusCode::RequestEntityTooLarge,
+            line: line!(),
+            column: column!(),
+        }));
+    }
+    let length = length as usize;
+
+    // Whatever followed the header is the start of the body, anything past
+    // the body belongs to the next request.
+    let mut body = buf.split_off(head_len);
+    *buf = if body.len() > length {
+        body.split_off(length)
+    } else {
+        Vec::new()
+    };
+    let start = body.len();
+    body.resize(length, 0);
+
+    if let Err(e) = conn.read_exact(&mut body[start..]) {
+        if e.kind() != io::ErrorKind::UnexpectedEof {
+            return Err(ReadError::Io(e));
+        }
+        return Err(ReadError::Invalid(ParsingError {
+            message: format!("Entity-Body is shorter than Content-Length {}", length),
+            status: protocol::StatusCode::BadRequest,
+            line: line!(),
+            column: column!(),
+        }));
+    }
+
+    if header.method == protocol::RequestMethod::Post
+        && header.media_type().as_deref() == Some(FORM_URLENCODED)
+    {
+        header.post_fields = match parse_form(&body) {
+            Some(fields) => fields,
+            None => {
+                return Err(ReadError::Invalid(ParsingError {
+                    message: "Malformed application/x-www-form-urlencoded body".to_string(),
+                    status: protocol::StatusCode::BadRequest,
+                    line: line!(),
+                    column: column!(),
+                }))
+            }
+        };
+    }
+
+    header.body = body;
+    Ok(header)
+}
+
+// The length of the body from a `Content-Length` field. A field sent more
+// than once holds every value separated by commas, they must all be the
+// same. A length too long for a `u64` is still a length, just too large to
+// read.
+fn content_length(value: &str) -> Option<u64> {
+    let mut lengths = value.split(',').map(|x| {
+        let x = x.trim();
+        match x.parse::<u64>() {
+            Err(_) if !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit()) => Ok(u64::MAX),
+            x => x,
+        }
+    });
+    let length = lengths.next()?.ok()?;
+    for x in lengths {
+        if x.ok()? != length {
+            return None;
+        }
+    }
+    Some(length)
+}
+
+// Parse the `length` byte `multipart/form-data` body following the
+// `head_len` byte header in `buf`, reading the rest of it from `conn`.
+fn read_multipart<R: Read>(
+    conn: &mut R,
+    buf: &mut Vec<u8>,
+    head_len: usize,
+    length: u64,
+    header: &mut Header,
+    config: &Config,
+) -> Result<(), ReadError> {
+    let max_total = config.upload_max_total.unwrap_or(DEFAULT_UPLOAD_MAX_TOTAL);
+    if length > max_total {
+        return Err(ReadError::Invalid(ParsingError {
+            message: format!(
+                "Upload of {} bytes is larger than {} bytes",
+                length, max_total
+            ),
+            status: protocol::StatusCode::RequestEntityTooLarge,
+            line: line!(),
+            column: column!(),
+        }));
+    }
+
+    let boundary = match header
+        .get_header_field(protocol::RequestField::ContentType)
+        .and_then(multipart::boundary)
+    {
+        Some(boundary) => boundary,
+        None => {
+            return Err(ReadError::Invalid(ParsingError {
+                message: "multipart/form-data without a boundary".to_string(),
+                status: protocol::StatusCode::BadRequest,
+                line: line!(),
+                column: column!(),
+            }))
+        }
+    };
+
+    // As with any body, what was read past it belongs to the next request
+    let mut start = buf.split_off(head_len);
+    *buf = if start.len() as u64 > length {
+        start.split_off(length as usize)
+    } else {
+        Vec::new()
+    };
+    let rest = length - start.len() as u64;
+
+    let mut body = io::Cursor::new(start).chain(conn.take(rest));
+    header.multipart = Some(multipart::parse(&mut body, &boundary, config)?);
+    Ok(())
+}
+
+// Decode the `name=value` pairs of a form, separated by `&`, in which `+`
+// stands for a space. Returns `None` if an escape is malformed or a name
+// or value is not UTF-8.
+fn parse_form(body: &[u8]) -> Option<HashMap<String, String>> {
+    let body = str::from_utf8(body).ok()?;
+    let decode = |s: &str| String::from_utf8(percent_decode(&s.replace('+', " "))?).ok();
+    let mut fields = HashMap::new();
+
+    for pair in body.split('&').filter(|x| !x.is_empty()) {
+        let (name, value) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, ""),
+        };
+        fields.insert(decode(name)?, decode(value)?);
+    }
+
+    Some(fields)
+}
+
+/// Decode `%XY` escapes in `s`. Returns `None` if an escape is malformed.
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::<u8>::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            let hex = str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Some(decoded)
+}
+
+/// Encode `s` for use in a URI path: everything but unreserved characters
+/// and `/` becomes a `%XY` escape.
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// Split an absolute request-URI such as `http://example.com/x?y` into
+// its host, port included, and the path and query after it.
+fn split_absolute_uri(uri: &str) -> Option<(&str, &str)> {
+    let scheme_end = uri.find("://")?;
+    let scheme = &uri[..scheme_end];
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let rest = &uri[scheme_end + 3..];
+    let host_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    Some((&rest[..host_end], &rest[host_end..]))
+}
+
+// Remove the port from a `host:port`, keeping the brackets of an IPv6
+// address.
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        host.split_inclusive(']').next().unwrap_or(host)
+    } else {
+        host.split(':').next().unwrap_or(host)
+    }
+}
+
+// Normalize the path of a request-target: decode it, then remove empty,
+// `.` and `..` segments (RFC 3986, Section 5.2.4) so the result can never
+// climb above `/`. A trailing slash is kept since it names a directory.
+// Paths that are not absolute, are not UTF-8 once decoded, or contain a
+// NUL byte are rejected.
+fn normalize_path(target: &str) -> Option<String> {
+    if !target.starts_with('/') {
+        return None;
+    }
+    let decoded = String::from_utf8(percent_decode(target)?).ok()?;
+    if decoded.contains('\0') {
+        return None;
+    }
+
+    let mut segments = Vec::<&str>::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            x => segments.push(x),
         }
     }
+
+    let mut path = format!("/{}", segments.join("/"));
+    let directory = decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    if directory && !segments.is_empty() {
+        path.push('/');
+    }
+
+    Some(path)
 }
 
 impl Header {
     pub fn new(buf: &[u8]) -> Self {
-        let request = str::from_utf8(&buf).unwrap().to_string();
         let mut header = Header::default();
+        let request = match str::from_utf8(buf) {
+            Ok(request) => request,
+            Err(_) => return header,
+        };
+        header.request_line = request.split("\r\n").next().unwrap_or("").to_string();
 
         // A simple request is defined as
         //      |GET /CRLF|
@@ -103,7 +534,32 @@ impl Header {
             _ => return header,
         }
 
-        header.path = method[1].to_string();
+        // An absolute request-URI names the host itself, `Host` is then
+        // ignored.
+        let target = match split_absolute_uri(method[1]) {
+            Some((host, target)) => {
+                header.host = Some(host.to_string());
+                target
+            }
+            None => method[1],
+        };
+
+        // The path is normalized before anything looks at it, a request
+        // for something like `/../x` can not be answered.
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], &target[i + 1..]),
+            None => (target, ""),
+        };
+        // `http://example.com` has an empty path, which stands for `/`
+        let path = match path {
+            "" if header.host.is_some() => "/",
+            x => x,
+        };
+        header.path = match normalize_path(path) {
+            Some(path) => path,
+            None => return header,
+        };
+        header.query = query.to_string();
 
         if method.len() == 2 {
             header.version = protocol::RequestVersion::SimpleRequest;
@@ -117,71 +573,159 @@ impl Header {
 
         // Remove the method line since we parsed it already
         parts.remove(0);
-        header.parse_fields(&parts);
+        if !header.parse_fields(&parts) {
+            return header;
+        }
+
+        if header.host.is_none() {
+            header.host = header.fields.get(&protocol::RequestField::Host).cloned();
+        }
+        // HTTP/1.1 clients must say which host they want
+        if header.version == protocol::RequestVersion::HTTP11 && header.host.is_none() {
+            return header;
+        }
+        header.host = header
+            .host
+            .map(|host| strip_port(&host).to_ascii_lowercase());
 
         // If we get here the request is valid
         header.valid = true;
         header
     }
 
-    /// Print the contents of the header field
-    /// in plain text to stdout. Used for development
-    pub fn print(&self) {
-        let method = protocol::method_to_string(&self.method);
-        let version = protocol::version_to_string(&self.version);
-
-        println!(
-            "Request Line: {}, Path: {}, Version {}",
-            method, self.path, version
-        );
-
-        println!("---- Known Fields ----");
-        for (key, value) in &self.fields {
-            let k = protocol::field_to_string(key);
-            println!("Field: {} -- Value: {}", k, value);
-        }
-        println!("---- Unknown Fields ----");
-        for (key, value) in &self.unknown_fields {
-            println!("Field: {} -- Value: {}", key, value);
-        }
-        if self.method == protocol::RequestMethod::Post {
-            println!("---- POST Fields ----");
-            for (key, value) in &self.post_fields {
-                println!("Name: {} -- Value: {}", key, value);
-            }
-        }
-    }
-
     /// Get the validity of the request. If this returns false, then all
     /// other header fields *might be* invalid.
     pub fn is_valid(&self) -> bool {
         self.valid
     }
 
-    /// Get the path of the request
+    /// Get the query of the request, everything after `?`. It is still
+    /// percent-encoded.
+    pub fn get_query(&self) -> &str {
+        &self.query
+    }
+
+    /// Get the host the request is for, from an absolute request-URI or
+    /// the `Host` field. It is lowercase and without the port.
+    pub fn get_host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Get the user who logged in to see a protected path, `None` for a
+    /// path anyone may see.
+    pub fn get_user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub(crate) fn set_user(&mut self, user: String) {
+        self.user = Some(user);
+    }
+
+    /// Get the path of the request. It is always absolute, percent-decoded
+    /// and free of `.` and `..` segments.
     pub fn get_path(&self) -> &str {
         &self.path
     }
 
+    /// Get the request line, exactly as it was sent.
+    pub fn get_request_line(&self) -> &str {
+        &self.request_line
+    }
+
     /// Get the method of the request
     pub fn get_method(&self) -> protocol::RequestMethod {
         self.method
     }
 
-    /*
-     * I'm not sure what the best way to deal with this is.
-     *
-     * Really this function is a `todo!()` function since right now TinyHTTP
-     * doesn't look at the request header fields.
-     *
-     * In the future this is planned to be used.
-     *
-     * Get a request header field's value.
-    #[allow(dead_code)]
+    /// Does the client want the connection kept open after the response?
+    /// HTTP/1.1 connections are persistent unless the client sends
+    /// `Connection: close`, HTTP/1.0 ones only with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self
+            .fields
+            .get(&protocol::RequestField::Connection)
+            .map(|x| x.to_ascii_lowercase());
+        let has = |token: &str| match &connection {
+            Some(x) => x.split(',').any(|t| t.trim() == token),
+            None => false,
+        };
+
+        match self.version {
+            protocol::RequestVersion::HTTP11 => !has("close"),
+            protocol::RequestVersion::HTTP1 => has("keep-alive"),
+            _ => false,
+        }
+    }
+
+    /// Get a request header field's value.
     pub fn get_header_field(&self, r: protocol::RequestField) -> Option<&str> {
         self.fields.get(&r).map(|x| &x[..])
     }
-    */
+
+    /// Get a header field that is not a `RequestField`, such as `Origin`.
+    /// The name is not case sensitive.
+    pub fn get_unknown_field(&self, name: &str) -> Option<&str> {
+        self.unknown_fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Get a parameter of the path, as named by the route the request
+    /// matched: `id` for `/users/:id`.
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|x| &x[..])
+    }
+
+    pub(crate) fn set_params(&mut self, params: HashMap<String, String>) {
+        self.params = params;
+    }
+
+    // Serve the request as if it was for `target`, a decoded path with an
+    // optional query. Returns false, changing nothing, if the path is not
+    // absolute.
+    pub(crate) fn rewrite(&mut self, target: &str) -> bool {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+        // Encoded first, a decoded `%` would not survive normalizing
+        let path = match normalize_path(&percent_encode(path)) {
+            Some(path) => path,
+            None => return false,
+        };
+
+        self.path = path;
+        if let Some(query) = query {
+            self.query = query.to_string();
+        }
+        true
+    }
+
+    /// Get the Entity-Body of the request, exactly as it was sent.
+    pub fn get_body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Get the body of a POST request by its `Content-Type`.
+    pub fn post_data(&self) -> PostData<'_> {
+        if let Some(multipart) = &self.multipart {
+            PostData::Multipart(multipart)
+        } else if self.body.is_empty() {
+            PostData::Empty
+        } else if self.media_type().as_deref() == Some(FORM_URLENCODED) {
+            PostData::Form(&self.post_fields)
+        } else {
+            PostData::Raw(&self.body)
+        }
+    }
+
+    // The media type of the body without its parameters, in lower case.
+    fn media_type(&self) -> Option<String> {
+        let content_type = self.get_header_field(protocol::RequestField::ContentType)?;
+        let media_type = content_type.split(';').next()?;
+        Some(media_type.trim().to_ascii_lowercase())
+    }
 
     // According to RFC1945 any unrecognized header fields are to
     // be treated as `Entity-Header` fields. Also the spec allows
@@ -189,59 +733,331 @@ impl Header {
     // communication recognize them.
     //
     // What ever the field is, we store it. Unknown fields are
-    // stored separately than known fields.
-    fn parse_fields(&mut self, parts: &[&str]) {
-        for i in parts {
-            let x: Vec<&str> = i.split(": ").collect();
-
-            if x.len() == 1 {
-                // Is there a better way to deal with this??
-                // We could be here for 2 reasons:
-                //   1: This is a POST request with fields, which we capture.
-                //   2. An invalid `field: parameter` with no parameter (invalid request)
-                let field: Vec<&str> = x[0].split('&').collect();
-                if !field.is_empty() {
-                    for i in &field {
-                        let j: Vec<&str> = i.split('=').collect();
-                        if j.len() == 2 {
-                            // POST field!
-                            self.post_fields.insert(j[0].to_string(), j[1].to_string());
-                        }
-                    }
-                }
-                continue;
-            }
+    // stored separately than known fields. A line that is not a
+    // `field: value` pair makes the whole request invalid.
+    fn parse_fields(&mut self, parts: &[&str]) -> bool {
+        for i in parts.iter().filter(|x| !x.is_empty()) {
+            let (name, value) = match i.find(':') {
+                Some(x) => (&i[..x], i[x + 1..].trim()),
+                None => return false,
+            };
 
-            let field = Header::field_to_type(x[0]);
+            let field = Header::field_to_type(name);
             if field == protocol::RequestField::Unknown {
                 self.unknown_fields
-                    .insert(x[0].to_string(), x[1].to_string());
+                    .insert(name.to_string(), value.to_string());
+            } else if field == protocol::RequestField::ContentLength {
+                // Every value is kept, they have to agree for the body to be
+                // read
+                self.fields
+                    .entry(field)
+                    .and_modify(|x| *x = format!("{}, {}", x, value))
+                    .or_insert_with(|| value.to_string());
             } else {
-                self.fields.insert(field, x[1].to_string());
+                self.fields.insert(field, value.to_string());
+            }
+        }
+
+        true
+    }
+
+    // Find the end of the request line and header fields in `buf`, that is
+    // the first byte of the Entity-Body. A full request ends its header with
+    // an empty line, a simple request has no header and ends after the
+    // request line. The first `scanned` bytes were searched before, only the
+    // last 3 of them can start a line ending with the new bytes. `line_end`
+    // keeps the end of the request line once it is found.
+    fn find_head_end(buf: &[u8], scanned: usize, line_end: &mut Option<usize>) -> Option<usize> {
+        let from = scanned.saturating_sub(3);
+        let find = |pattern: &[u8]| {
+            buf[from..]
+                .windows(pattern.len())
+                .position(|w| w == pattern)
+                .map(|i| from + i)
+        };
+
+        if line_end.is_none() {
+            let end = find(b"\r\n")?;
+            let request_line = String::from_utf8_lossy(&buf[..end]);
+            if request_line.split(' ').count() < 3 {
+                return Some(end + 2);
             }
+            *line_end = Some(end);
         }
+
+        find(b"\r\n\r\n").map(|i| i + 4)
     }
 
-    // Convert a request field to a known type.
+    // Convert a request field to a known type. Field names are not case
+    // sensitive.
     fn field_to_type(f: &str) -> protocol::RequestField {
-        match f {
-            "Allow" => protocol::RequestField::Allow,
-            "Authorization" => protocol::RequestField::Authorization,
-            "Content-Encoding" => protocol::RequestField::ContentEncoding,
-            "Content-Length" => protocol::RequestField::ContentLength,
-            "Content-Type" => protocol::RequestField::ContentType,
-            "Date" => protocol::RequestField::Date,
-            "Expires" => protocol::RequestField::Expires,
-            "From" => protocol::RequestField::FromField,
-            "If-Modified-Since" => protocol::RequestField::IfModifiedSince,
-            "Last-Modified" => protocol::RequestField::LastModified,
-            "Location" => protocol::RequestField::Location,
-            "Pragma" => protocol::RequestField::Pragma,
-            "Referer" => protocol::RequestField::Referer,
-            "Server" => protocol::RequestField::Server,
-            "User-Agent" => protocol::RequestField::UserAgent,
-            "WWW-Authenticate" => protocol::RequestField::WwwAuthenticate,
+        match f.to_ascii_lowercase().as_str() {
+            "allow" => protocol::RequestField::Allow,
+            "authorization" => protocol::RequestField::Authorization,
+            "content-encoding" => protocol::RequestField::ContentEncoding,
+            "content-length" => protocol::RequestField::ContentLength,
+            "content-type" => protocol::RequestField::ContentType,
+            "date" => protocol::RequestField::Date,
+            "expires" => protocol::RequestField::Expires,
+            "from" => protocol::RequestField::FromField,
+            "if-modified-since" => protocol::RequestField::IfModifiedSince,
+            "last-modified" => protocol::RequestField::LastModified,
+            "location" => protocol::RequestField::Location,
+            "pragma" => protocol::RequestField::Pragma,
+            "referer" => protocol::RequestField::Referer,
+            "server" => protocol::RequestField::Server,
+            "user-agent" => protocol::RequestField::UserAgent,
+            "www-authenticate" => protocol::RequestField::WwwAuthenticate,
+            "retry-after" => protocol::RequestField::RetryAfter,
+            "connection" => protocol::RequestField::Connection,
+            "etag" => protocol::RequestField::ETag,
+            "if-match" => protocol::RequestField::IfMatch,
+            "if-none-match" => protocol::RequestField::IfNoneMatch,
+            "if-unmodified-since" => protocol::RequestField::IfUnmodifiedSince,
+            "range" => protocol::RequestField::Range,
+            "if-range" => protocol::RequestField::IfRange,
+            "accept-ranges" => protocol::RequestField::AcceptRanges,
+            "content-range" => protocol::RequestField::ContentRange,
+            "keep-alive" => protocol::RequestField::KeepAlive,
+            "host" => protocol::RequestField::Host,
             _ => protocol::RequestField::Unknown,
         }
     }
 } // impl Header
+
+/// The contents of the header in plain text, as logged when
+/// `print_header_information` is set.
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let method = protocol::method_to_string(&self.method);
+        let version = protocol::version_to_string(&self.version);
+
+        writeln!(
+            f,
+            "Request Line: {}, Path: {}, Query: {}, Version {}",
+            method, self.path, self.query, version
+        )?;
+
+        writeln!(f, "---- Known Fields ----")?;
+        for (key, value) in &self.fields {
+            let k = protocol::field_to_string(key);
+            writeln!(f, "Field: {} -- Value: {}", k, value)?;
+        }
+        write!(f, "---- Unknown Fields ----")?;
+        for (key, value) in &self.unknown_fields {
+            write!(f, "\nField: {} -- Value: {}", key, value)?;
+        }
+        if !self.post_fields.is_empty() {
+            write!(f, "\n---- POST Fields ----")?;
+            for (key, value) in &self.post_fields {
+                write!(f, "\nName: {} -- Value: {}", key, value)?;
+            }
+        }
+        if let Some(multipart) = &self.multipart {
+            write!(f, "\n---- Multipart Parts ----")?;
+            for part in multipart.parts() {
+                write!(
+                    f,
+                    "\nName: {} -- File: {:?} -- Type: {:?} -- {} bytes",
+                    part.name(),
+                    part.filename(),
+                    part.content_type(),
+                    part.len()
+                )?;
+            }
+        }
+        if !self.body.is_empty() {
+            write!(f, "\n---- Entity-Body: {} bytes ----", self.body.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out `data` a few bytes per read, like a slow client.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(out.len()).min(self.data.len());
+            out[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn read(data: &[u8], step: usize) -> Result<Header, ReadError> {
+        let mut conn = Trickle { data, step };
+        read_request(&mut conn, &mut Vec::new(), &Config::default())
+    }
+
+    fn status(result: Result<Header, ReadError>) -> protocol::StatusCode {
+        match result {
+            Err(ReadError::Invalid(e)) => e.status,
+            x => panic!("expected an invalid request, got {:?}", x.map(|h| h.path)),
+        }
+    }
+
+    #[test]
+    fn head_split_across_reads() {
+        let request = b"GET /a/b HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        for step in 1..=4 {
+            let header = read(request, step).unwrap();
+            assert!(header.is_valid());
+            assert_eq!(header.get_path(), "/a/b");
+            assert_eq!(header.get_host(), Some("example.com"));
+        }
+    }
+
+    #[test]
+    fn simple_request() {
+        let header = read(b"GET /index.html\r\n", 1).unwrap();
+        assert!(header.is_valid());
+        assert_eq!(header.get_path(), "/index.html");
+    }
+
+    #[test]
+    fn body_and_next_request() {
+        let mut conn: &[u8] = b"POST / HTTP/1.0\r\nContent-Length: 3\r\n\r\nabcGET";
+        let mut buf = Vec::new();
+        let header = read_request(&mut conn, &mut buf, &Config::default()).unwrap();
+        assert_eq!(header.body, b"abc");
+        assert_eq!(buf, b"GET");
+    }
+
+    #[test]
+    fn dot_segments() {
+        assert_eq!(normalize_path("/a/./b/../c").as_deref(), Some("/a/c"));
+        assert_eq!(
+            normalize_path("/../../etc/passwd").as_deref(),
+            Some("/etc/passwd")
+        );
+        assert_eq!(normalize_path("/a//b///c").as_deref(), Some("/a/b/c"));
+        assert_eq!(normalize_path("/a/b/").as_deref(), Some("/a/b/"));
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn trailing_dot_segments() {
+        assert_eq!(normalize_path("/a/b/..").as_deref(), Some("/a/"));
+        assert_eq!(normalize_path("/a/b/.").as_deref(), Some("/a/b/"));
+        assert_eq!(normalize_path("/a/..").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/..").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn encoded_dot_segments() {
+        assert_eq!(
+            normalize_path("/%2e%2e/%2E%2E/etc").as_deref(),
+            Some("/etc")
+        );
+        assert_eq!(normalize_path("/a/%2e/b/.%2e/c").as_deref(), Some("/a/c"));
+        assert_eq!(
+            normalize_path("/a%2f..%2f..%2fetc").as_deref(),
+            Some("/etc")
+        );
+        assert_eq!(normalize_path("/a%2Fb").as_deref(), Some("/a/b"));
+        assert_eq!(
+            normalize_path("/%252e%252e/x").as_deref(),
+            Some("/%2e%2e/x")
+        );
+    }
+
+    #[test]
+    fn rejected_paths() {
+        assert_eq!(normalize_path("/a%00.html"), None);
+        assert_eq!(normalize_path("/a\0b"), None);
+        assert_eq!(normalize_path("/%ff"), None);
+        assert_eq!(normalize_path("/%zz"), None);
+        assert_eq!(normalize_path("/%2"), None);
+        assert_eq!(normalize_path("a/b"), None);
+        assert_eq!(normalize_path("%2fa"), None);
+    }
+
+    #[test]
+    fn request_path_is_normalized() {
+        let header = Header::new(b"GET /a/%2e%2e/../b%20c?x=1 HTTP/1.0\r\n\r\n");
+        assert!(header.is_valid());
+        assert_eq!(header.get_path(), "/b c");
+        assert_eq!(header.get_query(), "x=1");
+
+        let header = Header::new(b"GET /a%00 HTTP/1.0\r\n\r\n");
+        assert!(!header.is_valid());
+    }
+
+    #[test]
+    fn huge_content_length() {
+        for length in &[
+            usize::MAX.to_string(),
+            u64::MAX.to_string(),
+            "99999999999999999999999".to_string(),
+        ] {
+            let request = format!("POST / HTTP/1.0\r\nContent-Length: {}\r\n\r\n", length);
+            assert_eq!(
+                status(read(request.as_bytes(), 1024)),
+                protocol::StatusCode::RequestEntityTooLarge
+            );
+        }
+
+        let request = format!(
+            "POST / HTTP/1.0\r\nContent-Type: multipart/form-data; boundary=x\r\nContent-Length: {}\r\n\r\n",
+            u64::MAX
+        );
+        assert_eq!(
+            status(read(request.as_bytes(), 1024)),
+            protocol::StatusCode::RequestEntityTooLarge
+        );
+    }
+
+    #[test]
+    fn bad_content_length() {
+        let request = b"POST / HTTP/1.0\r\nContent-Length: -1\r\n\r\n";
+        assert_eq!(
+            status(read(request, 1024)),
+            protocol::StatusCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn repeated_content_length() {
+        let request = b"POST / HTTP/1.0\r\nContent-Length: 3\r\nContent-Length: 5\r\n\r\nabcde";
+        assert_eq!(
+            status(read(request, 1024)),
+            protocol::StatusCode::BadRequest
+        );
+        let request = b"POST / HTTP/1.0\r\nContent-Length: 3, 5\r\n\r\nabcde";
+        assert_eq!(
+            status(read(request, 1024)),
+            protocol::StatusCode::BadRequest
+        );
+
+        let request = b"POST / HTTP/1.0\r\nContent-Length: 3\r\ncontent-length: 3\r\n\r\nabc";
+        assert_eq!(read(request, 1024).unwrap().body, b"abc");
+    }
+
+    #[test]
+    fn transfer_encoding() {
+        let request =
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        assert_eq!(
+            status(read(request, 1024)),
+            protocol::StatusCode::NotImplemented
+        );
+    }
+
+    #[test]
+    fn header_too_large() {
+        let max = Config::default().max_buffer.unwrap_or(DEFAULT_MAX_BUFFER);
+        let request = format!("GET /{} HTTP/1.0\r\n\r\n", "a".repeat(max));
+        assert_eq!(
+            status(read(request.as_bytes(), READ_CHUNK)),
+            protocol::StatusCode::BadRequest
+        );
+    }
+}
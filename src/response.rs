@@ -5,24 +5,36 @@
 //! Spring 2021
 //!
 //! # Examples
-//! ```
+//! ```ignore
 //! TODO
 //! ```
 
-use chrono::{DateTime, Local, TimeZone, Utc};
 use std::collections::HashMap;
-use std::fs::{File, Metadata};
-use std::io::prelude::*;
-use std::path::Path;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
-use crate::configuration::CONFIG;
+use log::{debug, error, info};
+
+use crate::autoindex;
+use crate::body::{Body, Sink};
+use crate::conditional::{self, Validators};
+use crate::configuration::{
+    Config, DEFAULT_AUTOINDEX_FORMAT, DEFAULT_SERVER_TOKEN, DEFAULT_WRITE_BUFFER,
+};
+use crate::date;
+use crate::mime;
 use crate::protocol::*;
+use crate::range::{self, Ranges};
 use crate::request;
+use crate::router::Router;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ResponseError {
     pub message: String,
+    /// The status the client should be answered with
+    pub status: StatusCode,
     pub line: u32,
     pub column: u32,
 }
@@ -35,7 +47,7 @@ pub struct Response {
     pub status: StatusCode,
     pub version: RequestVersion,
     pub fields: HashMap<String, String>,
-    pub content: Vec<u8>,
+    pub body: Body,
 }
 
 impl Default for Response {
@@ -44,72 +56,244 @@ impl Default for Response {
             status: StatusCode::Unknown,
             version: RequestVersion::HTTP1,
             fields: HashMap::<String, String>::new(),
-            content: Vec::<u8>::new(),
+            body: Body::Empty,
         }
     }
 }
 
 impl Response {
-    /// Create a new Response structure with some default values.
-    pub fn new(h: &request::Header) -> Self {
+    /// Answer the request `h`. Requests matching a route of `router` go to
+    /// its handler, GET and HEAD requests for anything else are served from
+    /// `doc_root`.
+    pub fn new(h: &mut request::Header, config: &Config, router: &Router) -> Self {
         let mut response = Response::default();
 
         if !h.is_valid() {
-            response.status = StatusCode::BadRequest;
-            return response;
+            return Response::error(StatusCode::BadRequest, config);
         }
 
-        // Respond to the type of method
+        // Preconditions on methods that change a resource are checked before
+        // the method runs, GET and HEAD check them when reading the file. The
+        // file is only looked at when the client sent one.
         let m = h.get_method();
-        match m {
-            RequestMethod::Get => response.get_request(&h),
-            RequestMethod::Head => response.head_request(&h),
-            RequestMethod::Post => response.post_request(&h),
-            _ => response.unsupported_request(&h),
+        if m != RequestMethod::Get && m != RequestMethod::Head && conditional::has_preconditions(h)
+        {
+            let current = Response::current_validators(h.get_path(), config);
+            if let Some(status) = conditional::evaluate(h, current.as_ref()) {
+                return Response::error(status, config);
+            }
         }
 
+        // Respond to the route, or the type of method
+        match router.find(m, h.get_path()) {
+            Ok((handler, params)) => {
+                h.set_params(params);
+                return handler.handle(h).complete(config);
+            }
+            Err(allowed) => match m {
+                RequestMethod::Get => response.get_request(h, config),
+                // The body is dropped once the response is framed
+                RequestMethod::Head => response.get_request(h, config),
+                RequestMethod::Post => response.method_not_allowed(&allowed),
+                _ if !allowed.is_empty() => response.method_not_allowed(&allowed),
+                _ => response.unsupported_request(h),
+            },
+        }
+
+        if status_is_error(&response.status) {
+            response.error_page(config);
+        }
         response
     }
 
-    /// Format HTTP response to network ready data.
-    pub fn respond(&mut self) -> Vec<u8> {
-        let mut resp_header;
-        let mut r;
+    /// Finish a response a handler or middleware made. Without a status it
+    /// is a 200 OK and an error without a body gets its error page.
+    pub(crate) fn complete(mut self, config: &Config) -> Self {
+        if self.status == StatusCode::Unknown {
+            self.status = StatusCode::OK;
+        }
+        // An error document made by the handler is kept
+        if status_is_error(&self.status) && self.body.is_empty() {
+            self.error_page(config);
+        }
+        self
+    }
+
+    /// Create an empty Response with `status`, to be filled in by a handler.
+    pub fn from_status(status: StatusCode) -> Self {
+        Response {
+            status,
+            ..Response::default()
+        }
+    }
 
-        // If there is a bad request, just respond with error.
-        if self.status == StatusCode::BadRequest
-            || self.status == StatusCode::Unauthorized
-            || self.status == StatusCode::Forbidden
-            || self.status == StatusCode::NotFound
-        {
-            r = format! {
-                "{} {}\r\n\r\n",
-                version_to_string(&self.version),
-                status_to_string(&self.status)
-            };
+    /// Set a header field of the response, replacing any earlier value.
+    pub fn set_field(&mut self, field: RequestField, value: &str) {
+        self.fields
+            .insert(field_to_string(&field), value.to_string());
+    }
 
-            resp_header = r.as_bytes().to_vec();
+    /// Set a header field that is not a `RequestField`, such as
+    /// `Access-Control-Allow-Origin`, replacing any earlier value.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.fields.insert(format!("{}: ", name), value.to_string());
+    }
+
+    /// Set the body of the response and its `Content-Type`.
+    pub fn set_body<B: Into<Body>>(&mut self, content_type: &str, body: B) {
+        self.set_field(RequestField::ContentType, content_type);
+        self.body = body.into();
+    }
+
+    /// Create an error Response for `status` carrying its error page, for
+    /// requests that could not be handled at all.
+    pub fn error(status: StatusCode, config: &Config) -> Self {
+        let mut response = Response {
+            status,
+            ..Response::default()
+        };
+        response.error_page(config);
+        response
+    }
+
+    /// Write the response to `sink`, the header first and then the body a
+    /// `write_buffer` at a time. Returns the number of body bytes written.
+    pub fn respond<S: Sink>(&mut self, sink: &mut S, config: &Config) -> io::Result<u64> {
+        // Every response is framed by Content-Length so the client can
+        // find the end of it on a persistent connection.
+        let length = field_to_string(&RequestField::ContentLength);
+        if !self.fields.contains_key(&length) {
+            self.fields.insert(length, self.body.len().to_string());
+        }
+
+        // Sent with every response, unless already set
+        self.fields
+            .entry(field_to_string(&RequestField::Date))
+            .or_insert_with(|| date::format_http_date(SystemTime::now()));
+        let token = match &config.server_token {
+            Some(x) => x.as_str(),
+            None => DEFAULT_SERVER_TOKEN,
+        };
+        if !token.is_empty() {
+            self.fields
+                .entry(field_to_string(&RequestField::Server))
+                .or_insert_with(|| token.to_string());
+        }
+
+        let mut r = format!(
+            "{} {}\r\n",
+            version_to_string(&self.version),
+            status_to_string(&self.status)
+        );
+
+        for (key, value) in &self.fields {
+            r.push_str(&format!("{}{}\r\n", key, value));
+        }
+
+        r.push_str("\r\n");
+
+        let mut resp_header = r.into_bytes();
+        let body = match std::mem::take(&mut self.body) {
+            // A body in memory goes out with the header
+            Body::Bytes(mut bytes) => {
+                let len = bytes.len() as u64;
+                resp_header.append(&mut bytes);
+                sink.write_all(&resp_header)?;
+                return Ok(len);
+            }
+            body => body,
+        };
+
+        sink.write_all(&resp_header)?;
+        body.write_to(
+            sink,
+            config.write_buffer.unwrap_or(DEFAULT_WRITE_BUFFER),
+            config.sendfile.unwrap_or(true),
+        )
+    }
+
+    /// Tell the client whether the connection stays open after this
+    /// response. A persistent connection waits `timeout` seconds for the
+    /// next request and serves at most `max` more.
+    pub fn set_keep_alive(&mut self, keep_alive: bool, timeout: u64, max: usize) {
+        if keep_alive {
+            self.fields.insert(
+                field_to_string(&RequestField::Connection),
+                "keep-alive".to_string(),
+            );
+            self.fields.insert(
+                field_to_string(&RequestField::KeepAlive),
+                format!("timeout={}, max={}", timeout, max),
+            );
         } else {
-            // We have a request that needs a response
-            r = format!(
-                "{} {}\r\n",
-                version_to_string(&self.version),
-                status_to_string(&self.status)
+            self.fields.insert(
+                field_to_string(&RequestField::Connection),
+                "close".to_string(),
             );
+        }
+    }
 
-            for (key, value) in &self.fields {
-                r.push_str(&format!("{}{}\r\n", key, value));
-            }
+    // Replace whatever the response holds with the error document for its
+    // status: the page set in `error_pages` (or `custom_404`), or a short
+    // built in page when none is set or it can not be read.
+    fn error_page(&mut self, config: &Config) {
+        // Fields describing the resource that could not be sent go, fields
+        // describing the error itself such as `Retry-After` stay.
+        for field in &[
+            RequestField::ContentLength,
+            RequestField::ContentType,
+            RequestField::LastModified,
+            RequestField::ETag,
+            RequestField::AcceptRanges,
+        ] {
+            self.fields.remove(&field_to_string(field));
+        }
+        self.body = Body::Empty;
 
-            r.push_str("\r\n");
+        let code = (self.status as u16).to_string();
+        let mut page = match &config.error_pages {
+            Some(pages) => pages.get(&code),
+            None => None,
+        };
+        if page.is_none() && self.status == StatusCode::NotFound {
+            page = config.custom_404.as_ref();
+        }
 
-            resp_header = r.as_bytes().to_vec();
-            if !self.content.is_empty() {
-                resp_header.append(&mut self.content);
+        let mut content_type = "text/html; charset=utf-8".to_string();
+        let document = page.and_then(|page| {
+            let path = Response::resolve_path(page, config).ok()?;
+            content_type = mime::content_type(&path, config);
+            File::open(&path).and_then(Body::file).ok()
+        });
+
+        self.body = match document {
+            Some(document) => document,
+            None => {
+                let status = status_to_string(&self.status);
+                format!(
+                    concat!(
+                        "<!DOCTYPE html>\n",
+                        "<html>\n",
+                        "  <head>\n",
+                        "    <title>{}</title>\n",
+                        "  </head>\n",
+                        "  <body>\n",
+                        "    <h1>{}</h1>\n",
+                        "  </body>\n",
+                        "</html>\n",
+                    ),
+                    status, status
+                )
+                .into()
             }
-        }
+        };
 
-        resp_header
+        self.fields
+            .insert(field_to_string(&RequestField::ContentType), content_type);
+        self.fields.insert(
+            field_to_string(&RequestField::ContentLength),
+            self.body.len().to_string(),
+        );
     }
 
     fn get_last_modified(meta: &Metadata) -> Result<String, ResponseError> {
@@ -118,163 +302,581 @@ impl Response {
             Err(_) => {
                 return Err(ResponseError {
                     message: "Could not get modifed data on file".to_string(),
+                    status: StatusCode::InternalServerError,
                     line: line!(),
                     column: column!(),
                 })
             }
         };
 
-        let sec_from_epoch = lm.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        let local_dt = Local.timestamp(sec_from_epoch.as_secs() as i64, 0);
-        let utc_dt: DateTime<Utc> = DateTime::from(local_dt);
-
-        Ok(format!("{}", utc_dt.format("%a, %d %b %Y %H:%M:%S GMT")))
-    }
-
-    fn get_resource(&mut self, p: &str) -> Result<(), ResponseError> {
-        let doc_root = &CONFIG.doc_root;
-        let index = match &CONFIG.root_file {
-            Some(x) => &x,
-            None => match &CONFIG.default_root_file {
-                Some(x) => x,
-                _ => {
-                    return Err(ResponseError {
-                        message: "Could not find a default root file!".to_string(),
-                        line: line!(),
-                        column: column!(),
-                    })
-                }
-            },
+        Ok(date::format_http_date(lm))
+    }
+
+    fn get_resource(
+        &mut self,
+        req: &request::Header,
+        config: &Config,
+    ) -> Result<StatusCode, ResponseError> {
+        let path = Response::resolve_path(req.get_path(), config)?;
+        debug!("Path: {:?}", path);
+
+        if path.is_dir() {
+            self.get_directory(&path, req, config)
+        } else {
+            self.read_file(&path, req, config)
+        }
+    }
+
+    // Answer a request for the directory at `dir`. Without the trailing
+    // slash the client is sent to the path with it, so relative links in
+    // the page work. Otherwise the root file of the directory is sent, or
+    // a listing of it when `autoindex` is set.
+    fn get_directory(
+        &mut self,
+        dir: &Path,
+        req: &request::Header,
+        config: &Config,
+    ) -> Result<StatusCode, ResponseError> {
+        let p = req.get_path();
+        if !p.ends_with('/') {
+            let mut location = format!("{}/", request::percent_encode(p));
+            if !req.get_query().is_empty() {
+                location = format!("{}?{}", location, req.get_query());
+            }
+            self.set_field(RequestField::Location, &location);
+            return Ok(StatusCode::MovedPermanently);
+        }
+
+        // Specified by Config.toml -> root_file/default_root_file
+        if let Some(index) = config
+            .root_file
+            .as_ref()
+            .or(config.default_root_file.as_ref())
+        {
+            match Response::resolve_path(&format!("{}{}", p, index), config) {
+                Ok(path) if path.is_file() => return self.read_file(&path, req, config),
+                Err(e) if e.status != StatusCode::NotFound => return Err(e),
+                _ => (),
+            }
+        }
+
+        if !config.autoindex.unwrap_or(false) {
+            return Err(ResponseError {
+                message: format!("No root file in {}", dir.display()),
+                status: StatusCode::NotFound,
+                line: line!(),
+                column: column!(),
+            });
+        }
+        self.list_directory(dir, req, config)
+    }
+
+    // Make a listing of the directory at `dir` the body. Hidden files and
+    // files that would not be served are left out.
+    fn list_directory(
+        &mut self,
+        dir: &Path,
+        req: &request::Header,
+        config: &Config,
+    ) -> Result<StatusCode, ResponseError> {
+        let read = match fs::read_dir(dir) {
+            Ok(read) => read,
+            Err(x) => {
+                return Err(ResponseError {
+                    message: format!("Could not list {}! {}", dir.display(), x),
+                    status: StatusCode::InternalServerError,
+                    line: line!(),
+                    column: column!(),
+                })
+            }
         };
 
-        if p == "/" {
-            // Get the home page, specified by Config.toml -> doc_root/default_doc_root
-            let mut file = match File::open(&format!("{}/{}", doc_root, index)) {
-                Ok(file) => file,
-                Err(x) => {
-                    return Err(ResponseError {
-                        message: format!("Could not open file! {}", x),
-                        line: line!(),
-                        column: column!(),
-                    })
-                }
+        let mut entries = Vec::new();
+        for entry in read.flatten() {
+            let name = match entry.file_name().into_string() {
+                Ok(name) if !name.starts_with('.') => name,
+                _ => continue,
             };
-
-            let meta = match file.metadata() {
+            // Links leading out of `doc_root` are checked like a request
+            let path = match Response::resolve_path(&format!("{}{}", req.get_path(), name), config)
+            {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            let meta = match fs::metadata(&path) {
                 Ok(meta) => meta,
-                Err(_) => {
-                    return Err(ResponseError {
-                        message: "Could not get meta data on file".to_string(),
-                        line: line!(),
-                        column: column!(),
-                    })
-                }
+                Err(_) => continue,
             };
+            if !meta.is_dir() && Response::check_allowed(&path, config).is_err() {
+                continue;
+            }
+
+            entries.push(autoindex::Entry {
+                name,
+                is_dir: meta.is_dir(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                modified: meta.modified().ok(),
+            });
+        }
 
-            if let Ok(time) = Response::get_last_modified(&meta) {
-                self.fields
-                    .insert(field_to_string(&RequestField::LastModified), time);
+        let format = match &config.autoindex_format {
+            Some(x) => x.as_str(),
+            None => DEFAULT_AUTOINDEX_FORMAT,
+        };
+        let (content_type, listing) =
+            autoindex::render(req.get_path(), entries, req.get_query(), format);
+        // What would of been sent, for a HEAD request
+        self.set_field(RequestField::ContentLength, &listing.len().to_string());
+        self.set_body(content_type, listing);
+        Ok(StatusCode::OK)
+    }
+
+    // Find the file a request path names under `doc_root`. The request path
+    // is already free of `..` segments, but a symbolic link can still point
+    // out of the root. The canonical path must stay inside `doc_root`
+    // unless `follow_symlinks` is set.
+    fn resolve_path(p: &str, config: &Config) -> Result<PathBuf, ResponseError> {
+        let root = match fs::canonicalize(&config.doc_root) {
+            Ok(root) => root,
+            Err(x) => {
+                return Err(ResponseError {
+                    message: format!("Could not find doc_root {}! {}", config.doc_root, x),
+                    status: StatusCode::InternalServerError,
+                    line: line!(),
+                    column: column!(),
+                })
             }
+        };
 
-            match file.read_to_end(&mut self.content) {
-                Ok(size) => {
-                    self.fields.insert(
-                        field_to_string(&RequestField::ContentLength),
-                        size.to_string(),
-                    );
-                }
-                Err(x) => {
-                    return Err(ResponseError {
-                        message: format!("Could not read file! {}", x),
-                        line: line!(),
-                        column: column!(),
-                    })
-                }
+        let path = match fs::canonicalize(root.join(p.trim_start_matches('/'))) {
+            Ok(path) => path,
+            Err(x) => {
+                return Err(ResponseError {
+                    message: format!("Could not find {}! {}", p, x),
+                    status: StatusCode::NotFound,
+                    line: line!(),
+                    column: column!(),
+                })
+            }
+        };
+
+        if !path.starts_with(&root) && !config.follow_symlinks.unwrap_or(false) {
+            return Err(ResponseError {
+                message: format!("{} leads outside of doc_root", p),
+                status: StatusCode::Forbidden,
+                line: line!(),
+                column: column!(),
+            });
+        }
+
+        Ok(path)
+    }
+
+    // The validators of the file a request path names, `None` if there is
+    // no such file.
+    fn current_validators(p: &str, config: &Config) -> Option<Validators> {
+        let path = Response::resolve_path(p, config).ok()?;
+        let meta = fs::metadata(&path).ok()?;
+        Some(Validators::new(&path, &meta, config))
+    }
+
+    // Make the file at `path` the body and describe it in the fields.
+    // Returns the status to answer with, 200 OK or 304 Not Modified.
+    fn read_file(
+        &mut self,
+        path: &Path,
+        req: &request::Header,
+        config: &Config,
+    ) -> Result<StatusCode, ResponseError> {
+        Response::check_allowed(path, config)?;
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(x) => {
+                return Err(ResponseError {
+                    message: format!("Could not open file! {}", x),
+                    status: StatusCode::NotFound,
+                    line: line!(),
+                    column: column!(),
+                })
+            }
+        };
+
+        let meta = match file.metadata() {
+            Ok(meta) => meta,
+            Err(_) => {
+                return Err(ResponseError {
+                    message: "Could not get meta data on file".to_string(),
+                    status: StatusCode::InternalServerError,
+                    line: line!(),
+                    column: column!(),
+                })
+            }
+        };
+
+        if let Ok(time) = Response::get_last_modified(&meta) {
+            self.fields
+                .insert(field_to_string(&RequestField::LastModified), time);
+        }
+
+        self.fields.insert(
+            field_to_string(&RequestField::ContentType),
+            mime::content_type(path, config),
+        );
+
+        self.fields.insert(
+            field_to_string(&RequestField::AcceptRanges),
+            "bytes".to_string(),
+        );
+
+        let validators = Validators::new(path, &meta, config);
+        if let Some(etag) = &validators.etag {
+            self.fields
+                .insert(field_to_string(&RequestField::ETag), etag.to_string());
+        }
+
+        match conditional::evaluate(req, Some(&validators)) {
+            Some(StatusCode::NotModified) => {
+                // What would of been sent
+                self.fields.insert(
+                    field_to_string(&RequestField::ContentLength),
+                    meta.len().to_string(),
+                );
+                return Ok(StatusCode::NotModified);
+            }
+            Some(status) => {
+                return Err(ResponseError {
+                    message: format!("Precondition failed for {}", path.display()),
+                    status,
+                    line: line!(),
+                    column: column!(),
+                })
+            }
+            None => (),
+        }
+
+        // The file is read as it is sent
+        let len = meta.len();
+        self.fields.insert(
+            field_to_string(&RequestField::ContentLength),
+            len.to_string(),
+        );
+        self.body = Body::File {
+            file,
+            offset: 0,
+            len,
+        };
+
+        // Only a GET may ask for part of the file
+        let range = match req.get_header_field(RequestField::Range) {
+            Some(range) if req.get_method() == RequestMethod::Get => range,
+            _ => return Ok(StatusCode::OK),
+        };
+        if !conditional::if_range(req, &validators) {
+            return Ok(StatusCode::OK);
+        }
+
+        match range::parse_range(range, len) {
+            Ranges::Full => Ok(StatusCode::OK),
+            Ranges::Unsatisfiable => {
+                self.fields.insert(
+                    field_to_string(&RequestField::ContentRange),
+                    format!("bytes */{}", len),
+                );
+                Err(ResponseError {
+                    message: format!("Range {} is outside of {}", range, path.display()),
+                    status: StatusCode::RangeNotSatisfiable,
+                    line: line!(),
+                    column: column!(),
+                })
+            }
+            Ranges::Partial(ranges) => match self.select_ranges(&ranges, len) {
+                Ok(()) => Ok(StatusCode::PartialContent),
+                Err(x) => Err(ResponseError {
+                    message: format!("Could not read file! {}", x),
+                    status: StatusCode::InternalServerError,
+                    line: line!(),
+                    column: column!(),
+                }),
+            },
+        }
+    }
+
+    // Cut the file body down to `ranges` of its `len` bytes. A single range
+    // is sent as is, several as the parts of a `multipart/byteranges` body
+    // each carrying its own `Content-Type` and `Content-Range`.
+    fn select_ranges(&mut self, ranges: &[(u64, u64)], len: u64) -> io::Result<()> {
+        let file = match std::mem::take(&mut self.body) {
+            Body::File { file, .. } => file,
+            _ => return Err(io::Error::other("body is not a file")),
+        };
+        let content_type = field_to_string(&RequestField::ContentType);
+
+        if ranges.len() == 1 {
+            let (first, last) = ranges[0];
+            self.body = Body::File {
+                file,
+                offset: first,
+                len: last - first + 1,
             };
+            self.fields.insert(
+                field_to_string(&RequestField::ContentRange),
+                format!("bytes {}-{}/{}", first, last, len),
+            );
         } else {
-            // If the resource is not the index, we want to walk the directory
-            // tree and find it.
-            let p = format!("{}{}", CONFIG.doc_root, p);
-            let path = Path::new(&p);
-
-            println!("Path: {:?}", p);
-
-            if path.exists() {
-                let mut file = match File::open(path) {
-                    Ok(file) => file,
-                    Err(x) => {
-                        return Err(ResponseError {
-                            message: format!("Could not open file! {}", x),
-                            line: line!(),
-                            column: column!(),
-                        })
-                    }
-                };
-
-                let meta = match file.metadata() {
-                    Ok(meta) => meta,
-                    Err(_) => {
-                        return Err(ResponseError {
-                            message: "Could not get meta data on file".to_string(),
-                            line: line!(),
-                            column: column!(),
-                        })
-                    }
-                };
-
-                if let Ok(time) = Response::get_last_modified(&meta) {
-                    self.fields
-                        .insert(field_to_string(&RequestField::LastModified), time);
-                }
-
-                match file.read_to_end(&mut self.content) {
-                    Ok(size) => {
-                        self.fields.insert(
-                            field_to_string(&RequestField::ContentLength),
-                            size.to_string(),
-                        );
-                    }
-                    Err(x) => {
-                        return Err(ResponseError {
-                            message: format!("Could not read file! {}", x),
-                            line: line!(),
-                            column: column!(),
-                        })
-                    }
+            // Unlikely to turn up inside the file
+            let nanos = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                Ok(d) => d.as_nanos(),
+                Err(_) => 0,
+            };
+            let boundary = format!("tiny_http_{:x}", nanos);
+            let part_type = self.fields.get(&content_type).cloned();
+
+            let mut parts = Vec::new();
+            for &(first, last) in ranges {
+                let mut part = format!("\r\n--{}\r\n", boundary);
+                if let Some(x) = &part_type {
+                    part.push_str(&format!("{}{}\r\n", content_type, x));
                 }
+                part.push_str(&format!(
+                    "{}bytes {}-{}/{}\r\n\r\n",
+                    field_to_string(&RequestField::ContentRange),
+                    first,
+                    last,
+                    len
+                ));
+                parts.push(Body::from(part));
+                // Every part reads from its own offset
+                parts.push(Body::File {
+                    file: file.try_clone()?,
+                    offset: first,
+                    len: last - first + 1,
+                });
             }
+            parts.push(Body::from(format!("\r\n--{}--\r\n", boundary)));
+            self.body = Body::Parts(parts);
+
+            self.fields.insert(
+                content_type,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+        }
+
+        self.fields.insert(
+            field_to_string(&RequestField::ContentLength),
+            self.body.len().to_string(),
+        );
+        Ok(())
+    }
+
+    // Only files whose extension is on `image_list` or `file_list` are
+    // served, or any file when `serve_all` is set. Extensions on
+    // `deny_list` are never served.
+    fn check_allowed(path: &Path, config: &Config) -> Result<(), ResponseError> {
+        let ext = path
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_ascii_lowercase());
+        let listed = |list: &[String]| match &ext {
+            Some(ext) => list.iter().any(|x| x.to_ascii_lowercase() == *ext),
+            None => false,
+        };
+
+        let denied = match &config.deny_list {
+            Some(list) => listed(list),
+            None => false,
+        };
+        let allowed = config.serve_all.unwrap_or(false)
+            || listed(&config.image_list)
+            || listed(&config.file_list);
+
+        if denied || !allowed {
+            return Err(ResponseError {
+                message: format!("Not allowed to serve {}", path.display()),
+                status: StatusCode::Forbidden,
+                line: line!(),
+                column: column!(),
+            });
         }
 
         Ok(())
     }
 
     // Handle a GET request from a client
-    fn get_request(&mut self, req: &request::Header) {
-        match self.get_resource(req.get_path()) {
-            Ok(_) => self.status = StatusCode::OK,
-            Err(_) => {
-                self.status = StatusCode::NotFound;
+    fn get_request(&mut self, req: &request::Header, config: &Config) {
+        match self.get_resource(req, config) {
+            Ok(status) => self.status = status,
+            Err(e) => {
+                log_error(req, &e);
+                self.status = e.status;
             }
         }
 
         // Are their any fields we want to look at?
     }
 
-    fn head_request(&mut self, req: &request::Header) {
-        match self.get_resource(req.get_path()) {
-            Ok(_) => self.status = StatusCode::OK,
-            Err(_) => {
-                self.status = StatusCode::NotFound;
-            }
+    /// Frame the response to a request made with `m`, once nothing will
+    /// change it. `Content-Length` is the length of the body, which the
+    /// `after` hooks of middleware may of replaced, except for a 304 that
+    /// gives the length of the file without sending it. A HEAD request only
+    /// gets the fields a GET request would.
+    pub(crate) fn frame(&mut self, m: RequestMethod) {
+        if self.status != StatusCode::NotModified {
+            self.fields.insert(
+                field_to_string(&RequestField::ContentLength),
+                self.body.len().to_string(),
+            );
+        }
+        if m == RequestMethod::Head {
+            self.body = Body::Empty;
         }
-        self.content.clear();
     }
 
-    fn post_request(&mut self, _req: &request::Header) {
-        todo!()
+    // The path can not be requested with this method. Files under
+    // `doc_root` can be read, `allowed` are the methods routes take.
+    fn method_not_allowed(&mut self, allowed: &[RequestMethod]) {
+        let mut methods = vec![RequestMethod::Get, RequestMethod::Head];
+        for m in allowed {
+            if !methods.contains(m) {
+                methods.push(*m);
+            }
+        }
+        let methods: Vec<String> = methods.iter().map(method_to_string).collect();
+
+        self.status = StatusCode::MethodNotAllowed;
+        self.set_field(RequestField::Allow, &methods.join(", "));
     }
 
+    // Methods beyond GET, HEAD and POST are recognized but not carried out.
     fn unsupported_request(&mut self, _req: &request::Header) {
-        todo!()
+        self.status = StatusCode::NotImplemented;
+    }
+}
+
+// Report why a request for a file failed. Failures of the server are
+// errors, anything the client asked for wrong is only of interest.
+fn log_error(req: &request::Header, e: &ResponseError) {
+    let message = format!(
+        "{} {}: {} (line {}, column {})",
+        method_to_string(&req.get_method()),
+        req.get_path(),
+        e.message,
+        e.line,
+        e.column
+    );
+    if e.status as u16 >= 500 {
+        error!("{}", message);
+    } else {
+        info!("{}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // A `doc_root` holding `index.html` and `sub/page.html`, next to a
+    // `secret.txt` outside of it. Removed when dropped.
+    struct Site {
+        dir: PathBuf,
+        config: Config,
+    }
+
+    impl Site {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("tiny_http_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            let root = dir.join("root");
+            fs::create_dir_all(root.join("sub")).unwrap();
+            fs::write(root.join("index.html"), "index").unwrap();
+            fs::write(root.join("sub/page.html"), "page").unwrap();
+            fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+            let config = Config {
+                doc_root: root.to_string_lossy().into_owned(),
+                ..Config::default()
+            };
+            Site { dir, config }
+        }
+
+        fn root(&self) -> PathBuf {
+            fs::canonicalize(self.dir.join("root")).unwrap()
+        }
+
+        fn status(&self, p: &str) -> StatusCode {
+            match Response::resolve_path(p, &self.config) {
+                Ok(_) => StatusCode::OK,
+                Err(e) => e.status,
+            }
+        }
+    }
+
+    impl Drop for Site {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn paths_inside_doc_root() {
+        let site = Site::new("inside");
+        let path = Response::resolve_path("/sub/page.html", &site.config).unwrap();
+        assert_eq!(path, site.root().join("sub/page.html"));
+        let path = Response::resolve_path("/", &site.config).unwrap();
+        assert_eq!(path, site.root());
+        assert_eq!(site.status("/missing.html"), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn request_paths_stay_in_doc_root() {
+        let site = Site::new("escape");
+        for target in &[
+            "/../secret.txt",
+            "/sub/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/sub%2f..%2f..%2fsecret.txt",
+            "/sub/..",
+        ] {
+            let header =
+                request::Header::new(format!("GET {} HTTP/1.0\r\n\r\n", target).as_bytes());
+            assert!(header.is_valid(), "{}", target);
+            assert_ne!(
+                Response::resolve_path(header.get_path(), &site.config).ok(),
+                Some(site.dir.join("secret.txt").canonicalize().unwrap()),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_doc_root() {
+        use std::os::unix::fs::symlink;
+
+        let mut site = Site::new("symlink");
+        symlink(site.dir.join("secret.txt"), site.root().join("link.txt")).unwrap();
+        symlink(&site.dir, site.root().join("up")).unwrap();
+        symlink(
+            site.root().join("index.html"),
+            site.root().join("home.html"),
+        )
+        .unwrap();
+
+        assert_eq!(site.status("/link.txt"), StatusCode::Forbidden);
+        assert_eq!(site.status("/up/secret.txt"), StatusCode::Forbidden);
+        assert_eq!(site.status("/home.html"), StatusCode::OK);
+
+        site.config.follow_symlinks = Some(true);
+        assert_eq!(site.status("/link.txt"), StatusCode::OK);
+        assert_eq!(site.status("/up/secret.txt"), StatusCode::OK);
+    }
+
+    #[test]
+    fn missing_doc_root() {
+        let mut site = Site::new("missing");
+        site.config.doc_root = site.dir.join("nowhere").to_string_lossy().into_owned();
+        assert_eq!(site.status("/"), StatusCode::InternalServerError);
     }
 }
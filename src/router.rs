@@ -0,0 +1,165 @@
+//! Request Router
+//!
+//! Sends requests to user handlers by method and path. A pattern is
+//! matched segment by segment against the request path: `:name` matches
+//! any one segment and records it as the parameter `name`, `*` or `*name`
+//! at the end matches the rest of the path. Requests no route matches are
+//! answered with the files under `doc_root`.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::collections::HashMap;
+
+use crate::handler::Handler;
+use crate::protocol::RequestMethod;
+
+/// Parameters taken from a path, by name.
+type Params = HashMap<String, String>;
+
+/// Routes requests to handlers, in the order the routes were added.
+///
+/// ```no_run
+/// use tiny_http::{Header, Response, Router, Server, StatusCode};
+///
+/// fn user(req: &Header) -> Response {
+///     let mut res = Response::from_status(StatusCode::OK);
+///     res.set_body("text/plain", format!("user {:?}", req.get_param("id")));
+///     res
+/// }
+///
+/// let router = Router::new().get("/api/users/:id", user);
+/// let server = Server::builder().router(router).build().unwrap();
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+struct Route {
+    methods: Vec<RequestMethod>,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    // The rest of the path, under the name if it has one
+    Rest(Option<String>),
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Answer GET requests, and HEAD requests for the same resource, for
+    /// paths matching `pattern` with `handler`.
+    pub fn get<H: Handler + 'static>(self, pattern: &str, handler: H) -> Self {
+        self.route(&[RequestMethod::Get, RequestMethod::Head], pattern, handler)
+    }
+
+    /// Answer POST requests for paths matching `pattern` with `handler`.
+    pub fn post<H: Handler + 'static>(self, pattern: &str, handler: H) -> Self {
+        self.route(&[RequestMethod::Post], pattern, handler)
+    }
+
+    /// Answer requests with any of `methods` for paths matching `pattern`
+    /// with `handler`.
+    ///
+    /// # Panics
+    ///
+    /// If a `*` segment is not the last one of `pattern`.
+    pub fn route<H: Handler + 'static>(
+        mut self,
+        methods: &[RequestMethod],
+        pattern: &str,
+        handler: H,
+    ) -> Self {
+        let segments: Vec<&str> = pattern.split('/').filter(|x| !x.is_empty()).collect();
+        let mut compiled = Vec::new();
+
+        for (i, segment) in segments.iter().enumerate() {
+            compiled.push(if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                if i + 1 != segments.len() {
+                    panic!("`*` must be the last segment of the route {}", pattern);
+                }
+                Segment::Rest(Some(name.to_string()).filter(|x| !x.is_empty()))
+            } else {
+                Segment::Literal(segment.to_string())
+            });
+        }
+
+        self.routes.push(Route {
+            methods: methods.to_vec(),
+            pattern: compiled,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Find the handler for a `method` request of `path` and the parameters
+    /// taken from the path. When no route has both, the error holds the
+    /// methods the routes matching `path` do accept, if any.
+    pub(crate) fn find(
+        &self,
+        method: RequestMethod,
+        path: &str,
+    ) -> Result<(&dyn Handler, Params), Vec<RequestMethod>> {
+        let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            let params = match route.matches(&segments) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.methods.contains(&method) {
+                return Ok((route.handler.as_ref(), params));
+            }
+            for m in &route.methods {
+                if !allowed.contains(m) {
+                    allowed.push(*m);
+                }
+            }
+        }
+
+        Err(allowed)
+    }
+}
+
+impl Route {
+    // The parameters of the path if it matches the pattern.
+    fn matches(&self, segments: &[&str]) -> Option<Params> {
+        let mut params = HashMap::new();
+
+        for (i, segment) in self.pattern.iter().enumerate() {
+            match segment {
+                Segment::Rest(name) => {
+                    if let Some(name) = name {
+                        params.insert(name.clone(), segments[i.min(segments.len())..].join("/"));
+                    }
+                    return Some(params);
+                }
+                Segment::Literal(x) => {
+                    if segments.get(i) != Some(&x.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), segments.get(i)?.to_string());
+                }
+            }
+        }
+
+        if segments.len() == self.pattern.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Redirect and Rewrite Rules
+//!
+//! Rules from the `[[redirect]]` and `[[rewrite]]` tables of the
+//! configuration. A redirect answers with the new location of a path, a
+//! rewrite serves the request from another path without the client
+//! knowing. Rules are tried in the order they are listed and the first one
+//! matching is used, redirects before rewrites.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use log::{debug, warn};
+use regex::{Captures, Regex};
+
+use crate::configuration::{Config, Rule, DEFAULT_REDIRECT_STATUS};
+use crate::protocol::{RequestField, StatusCode};
+use crate::request::{self, Header};
+use crate::response::Response;
+use crate::TinyHttpError;
+
+/// The redirects and rewrites of a server.
+pub struct Rules {
+    redirects: Vec<(Matcher, StatusCode)>,
+    rewrites: Vec<Matcher>,
+}
+
+struct Matcher {
+    pattern: Pattern,
+    to: String,
+}
+
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Rules {
+    /// Compile the rules of `config`.
+    pub fn new(config: &Config) -> Result<Self, TinyHttpError> {
+        let mut redirects = Vec::new();
+        for rule in config.redirect.iter().flatten() {
+            let status = match rule.status.unwrap_or(DEFAULT_REDIRECT_STATUS) {
+                301 => StatusCode::MovedPermanently,
+                302 => StatusCode::MovedTemporarily,
+                303 => StatusCode::SeeOther,
+                307 => StatusCode::TemporaryRedirect,
+                308 => StatusCode::PermanentRedirect,
+                x => {
+                    return Err(TinyHttpError {
+                        message: format!("{} is not a redirect status", x),
+                    })
+                }
+            };
+            redirects.push((Matcher::new(rule)?, status));
+        }
+
+        let mut rewrites = Vec::new();
+        for rule in config.rewrite.iter().flatten() {
+            rewrites.push(Matcher::new(rule)?);
+        }
+
+        Ok(Rules {
+            redirects,
+            rewrites,
+        })
+    }
+
+    /// Answer `req` with a redirect if one matches, otherwise rewrite its
+    /// path if a rewrite matches.
+    pub fn apply(&self, req: &mut Header, config: &Config) -> Option<Response> {
+        if !req.is_valid() {
+            return None;
+        }
+
+        for (matcher, status) in &self.redirects {
+            let mut location = match matcher.target(req.get_path(), true) {
+                Some(x) => x,
+                None => continue,
+            };
+            if !location.contains('?') && !req.get_query().is_empty() {
+                location = format!("{}?{}", location, req.get_query());
+            }
+
+            let mut res = Response::from_status(*status);
+            res.set_field(RequestField::Location, &location);
+            return Some(res.complete(config));
+        }
+
+        for matcher in &self.rewrites {
+            let target = match matcher.target(req.get_path(), false) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            if req.rewrite(&target) {
+                debug!("Rewrote the request to {}", target);
+            } else {
+                warn!(
+                    "Could not rewrite {} to {}, not a path",
+                    req.get_path(),
+                    target
+                );
+            }
+            break;
+        }
+
+        None
+    }
+}
+
+impl Matcher {
+    fn new(rule: &Rule) -> Result<Self, TinyHttpError> {
+        let pattern = match (&rule.exact, &rule.prefix, &rule.regex) {
+            (Some(x), None, None) => Pattern::Exact(x.clone()),
+            (None, Some(x), None) => Pattern::Prefix(x.clone()),
+            (None, None, Some(x)) => match Regex::new(x) {
+                Ok(regex) => Pattern::Regex(regex),
+                Err(e) => {
+                    return Err(TinyHttpError {
+                        message: format!("Invalid regex {}! {}", x, e),
+                    })
+                }
+            },
+            _ => {
+                return Err(TinyHttpError {
+                    message: format!(
+                        "The rule to {} needs one of exact, prefix or regex",
+                        rule.to
+                    ),
+                })
+            }
+        };
+
+        Ok(Matcher {
+            pattern,
+            to: rule.to.clone(),
+        })
+    }
+
+    // Where `path` goes if the rule matches it. Parts of the path put in
+    // the target are percent-encoded when `encode` is set, for a target
+    // sent to the client.
+    fn target(&self, path: &str, encode: bool) -> Option<String> {
+        let part = |x: &str| {
+            if encode {
+                request::percent_encode(x)
+            } else {
+                x.to_string()
+            }
+        };
+
+        match &self.pattern {
+            Pattern::Exact(x) if path == x => Some(self.to.clone()),
+            Pattern::Prefix(x) => path
+                .strip_prefix(x.as_str())
+                .map(|rest| format!("{}{}", self.to, part(rest))),
+            Pattern::Regex(regex) => regex
+                .captures(path)
+                .map(|caps| expand(&self.to, &caps, part)),
+            _ => None,
+        }
+    }
+}
+
+// Put the groups of `caps` in place of `$1` or `${1}` and `${name}` in
+// `to`, `$$` is a `$`.
+fn expand<F: Fn(&str) -> String>(to: &str, caps: &Captures, part: F) -> String {
+    let mut expanded = String::with_capacity(to.len());
+    let mut rest = to;
+
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else if rest.starts_with('$') {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            (&rest[..end], end)
+        };
+
+        if len == 0 {
+            // Not a reference, keep the `$`
+            expanded.push('$');
+            continue;
+        }
+        let group = match name.parse::<usize>() {
+            Ok(i) => caps.get(i),
+            Err(_) => caps.name(name),
+        };
+        if let Some(group) = group {
+            expanded.push_str(&part(group.as_str()));
+        }
+        rest = &rest[len..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
@@ -0,0 +1,733 @@
+//! HTTP Server
+//!
+//! Binds the listeners, hands accepted connections to the worker pool and
+//! serves the requests on each connection. Every server carries its own
+//! `Config` so several can run side by side in one process. With the `tls`
+//! feature a server can listen for HTTPS, alongside or instead of HTTP.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::io::{ErrorKind, Read};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::access_log::{AccessLog, Entry};
+use crate::auth::Realms;
+use crate::body::Sink;
+use crate::configuration::{
+    Config, DEFAULT_ACCEPT_QUEUE, DEFAULT_DRAIN_TIMEOUT, DEFAULT_KEEP_ALIVE_MAX,
+    DEFAULT_KEEP_ALIVE_TIMEOUT, DEFAULT_RETRY_AFTER, DEFAULT_WORKER_THREADS, DEFAULT_WRITE_TIMEOUT,
+};
+use crate::error_log;
+use crate::handler::Handler;
+use crate::middleware::Middleware;
+use crate::pool::ThreadPool;
+use crate::protocol::{field_to_string, RequestField, StatusCode};
+use crate::request::{self, Header, ReadError};
+use crate::response::Response;
+use crate::router::Router;
+use crate::rules::Rules;
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::vhost::VirtualHosts;
+use crate::{Result, TinyHttpError};
+
+/// How long the listening thread sleeps when there is no connection
+/// waiting before it checks for a shutdown again.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// How often the access log is written out.
+const LOG_FLUSH: Duration = Duration::from_secs(1);
+
+// Everything a connection needs from the server that accepted it.
+struct Context {
+    config: Config,
+    running: AtomicBool,
+    router: Router,
+    middleware: Vec<Box<dyn Middleware>>,
+    access_log: Option<AccessLog>,
+    vhosts: VirtualHosts,
+    rules: Rules,
+    auth: Realms,
+}
+
+// How the connections accepted by a listener are served.
+#[derive(Clone)]
+enum Scheme {
+    Http,
+    // Every request is redirected to HTTPS on this port
+    Redirect(u16),
+    #[cfg(feature = "tls")]
+    Https(Arc<rustls::ServerConfig>),
+}
+
+/// Builds a `Server`. The configuration starts out as `Config::default()`
+/// and can be replaced whole, read from a file or a string, or changed
+/// option by option.
+///
+/// ```no_run
+/// let server = tiny_http::Server::builder()
+///     .config_file("Config.toml")
+///     .port(8081)
+///     .build()
+///     .unwrap();
+/// server.run().unwrap();
+/// ```
+pub struct ServerBuilder {
+    config: Config,
+    error: Option<TinyHttpError>,
+    router: Router,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+/// A bound server, ready to run. Nothing is accepted until `run` or
+/// `start` is called.
+pub struct Server {
+    // The listener on `port` comes first
+    listeners: Vec<(TcpListener, Scheme)>,
+    context: Arc<Context>,
+}
+
+/// Handle to a server running in the background, returned by `start`.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    tls_addr: Option<SocketAddr>,
+    context: Arc<Context>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl ServerBuilder {
+    /// Use `config` as the whole configuration.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Read the configuration from the TOML file at `path`.
+    pub fn config_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        match Config::from_file(path) {
+            Ok(config) => self.config = config,
+            Err(e) => self.fail(e.message),
+        }
+        self
+    }
+
+    /// Parse the configuration from a TOML document.
+    pub fn config_str(mut self, c: &str) -> Self {
+        match Config::from_toml(c) {
+            Ok(config) => self.config = config,
+            Err(e) => self.fail(e.message),
+        }
+        self
+    }
+
+    /// Change any option of the configuration.
+    pub fn configure<F: FnOnce(&mut Config)>(mut self, f: F) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// The host or address to listen on.
+    pub fn host(mut self, host: &str) -> Self {
+        self.config.host = host.to_string();
+        self
+    }
+
+    /// The port to listen on, 0 lets the system pick a free one.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Where the resources of the server are stored.
+    pub fn doc_root(mut self, doc_root: &str) -> Self {
+        self.config.doc_root = doc_root.to_string();
+        self
+    }
+
+    /// Send requests matching the routes of `router` to their handlers,
+    /// replacing any routes set before.
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Answer every POST request with `handler`. Without a handler a POST
+    /// gets a 405 Method Not Allowed.
+    pub fn post_handler<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.router = std::mem::take(&mut self.router).post("/*", handler);
+        self
+    }
+
+    /// Run `middleware` around every request, after the middleware added
+    /// before it.
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Bind the listeners. Fails if the configuration could not be read,
+    /// the certificates could not be loaded or an address is not
+    /// available.
+    pub fn build(self) -> Result<Server> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let config = self.config;
+        error_log::init(&config)?;
+        let access_log = AccessLog::open(&config)?;
+        let vhosts = VirtualHosts::new(&config)?;
+        let rules = Rules::new(&config)?;
+        let auth = Realms::new(&config)?;
+
+        let https = https(&config)?;
+        if https.is_none() && config.tls_port.is_some() {
+            return Err(TinyHttpError {
+                message: "tls_port is set without a cert_file".to_string(),
+            });
+        }
+        let redirect = config.https_redirect.unwrap_or(false);
+        if redirect && config.tls_port.is_none() {
+            return Err(TinyHttpError {
+                message: "https_redirect is set without a tls_port".to_string(),
+            });
+        }
+
+        let listener = bind(&config.host, config.port)?;
+        let listeners = match (https, config.tls_port) {
+            (Some(https), Some(tls_port)) => {
+                let tls_listener = bind(&config.host, tls_port)?;
+                // With port 0 the system picked the port to redirect to
+                let plain = match redirect {
+                    true => Scheme::Redirect(address(&tls_listener)?.port()),
+                    false => Scheme::Http,
+                };
+                vec![(listener, plain), (tls_listener, https)]
+            }
+            (Some(https), None) => vec![(listener, https)],
+            (None, _) => vec![(listener, Scheme::Http)],
+        };
+
+        Ok(Server {
+            listeners,
+            context: Arc::new(Context {
+                config,
+                running: AtomicBool::new(true),
+                router: self.router,
+                middleware: self.middleware,
+                access_log,
+                vhosts,
+                rules,
+                auth,
+            }),
+        })
+    }
+
+    // Keep the first error, it is reported by `build`.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(TinyHttpError { message });
+        }
+    }
+}
+
+impl Server {
+    /// Start building a server.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            config: Config::default(),
+            error: None,
+            router: Router::new(),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// The address the server is listening on, `port` of the
+    /// configuration.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        address(&self.listeners[0].0)
+    }
+
+    /// The address the server is listening for HTTPS on, if it is.
+    pub fn tls_addr(&self) -> Result<Option<SocketAddr>> {
+        match self.listeners.iter().find(|(_, scheme)| scheme.is_https()) {
+            Some((listener, _)) => address(listener).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Serve connections on the current thread. This blocks for as long as
+    /// the server runs.
+    pub fn run(self) -> Result<()> {
+        self.start()?.wait()
+    }
+
+    /// Serve connections in the background and return a handle to control
+    /// the server.
+    pub fn start(self) -> Result<ServerHandle> {
+        // Accept without blocking so the listening thread can notice a shutdown
+        for (listener, _) in &self.listeners {
+            if let Err(e) = listener.set_nonblocking(true) {
+                return Err(TinyHttpError {
+                    message: format!("Could not set up the listener! {}", e),
+                });
+            }
+        }
+        let addr = self.local_addr()?;
+        let tls_addr = self.tls_addr()?;
+
+        let listeners = self.listeners;
+        let context = Arc::clone(&self.context);
+        let thread = thread::spawn(move || listen_on(listeners, context));
+
+        Ok(ServerHandle {
+            addr,
+            tls_addr,
+            context: self.context,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl ServerHandle {
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The address the server is listening for HTTPS on, if it is.
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.tls_addr
+    }
+
+    /// Stop accepting connections and wait for the ones being served to
+    /// finish. Connections still running after `drain_timeout` seconds are
+    /// abandoned.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.context.running.store(false, Ordering::SeqCst);
+        self.join()
+    }
+
+    /// Block until the server stops.
+    pub fn wait(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(result) => result,
+                Err(_) => Err(TinyHttpError {
+                    message: "The listening thread panicked".to_string(),
+                }),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Scheme {
+    fn is_https(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls")]
+            Scheme::Https(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn bind(host: &str, port: u16) -> Result<TcpListener> {
+    match TcpListener::bind(format!("{}:{}", host, port)) {
+        Ok(listener) => Ok(listener),
+        Err(e) => Err(TinyHttpError {
+            message: format!("Could not bind {}:{}! {}", host, port, e),
+        }),
+    }
+}
+
+fn address(listener: &TcpListener) -> Result<SocketAddr> {
+    match listener.local_addr() {
+        Ok(addr) => Ok(addr),
+        Err(e) => Err(TinyHttpError {
+            message: format!("Could not get the listening address! {}", e),
+        }),
+    }
+}
+
+// How HTTPS is served, if `cert_file` is set.
+#[cfg(feature = "tls")]
+fn https(config: &Config) -> Result<Option<Scheme>> {
+    Ok(tls::server_config(config)?.map(Scheme::Https))
+}
+
+#[cfg(not(feature = "tls"))]
+fn https(config: &Config) -> Result<Option<Scheme>> {
+    match config.cert_file {
+        Some(_) => Err(TinyHttpError {
+            message: "cert_file is set but TinyHTTP was built without the tls feature".to_string(),
+        }),
+        None => Ok(None),
+    }
+}
+
+// Listen for incomming connections from a client. Once a connection is
+// established it is queued for the next free worker thread.
+fn listen_on(listeners: Vec<(TcpListener, Scheme)>, context: Arc<Context>) -> Result<()> {
+    let config = &context.config;
+    let serving = Arc::clone(&context);
+    let mut pool = ThreadPool::new(
+        config.worker_threads.unwrap_or(DEFAULT_WORKER_THREADS),
+        config.accept_queue.unwrap_or(DEFAULT_ACCEPT_QUEUE),
+        move |(conn, scheme)| new_connection(conn, scheme, &serving),
+    );
+
+    // TODO
+    //  listen.set_ttl(X)
+
+    let mut flushed = Instant::now();
+    while context.running.load(Ordering::SeqCst) {
+        if let Some(log) = &context.access_log {
+            if flushed.elapsed() >= LOG_FLUSH || log.reopen_requested() {
+                log.flush();
+                flushed = Instant::now();
+            }
+        }
+
+        let mut idle = true;
+        for (listen, scheme) in &listeners {
+            match listen.accept() {
+                Ok((stream, _)) => {
+                    idle = false;
+                    // Only the listener is non-blocking
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        warn!("Error connecting to client! {}", e);
+                        continue;
+                    }
+                    if let Err((stream, scheme)) = pool.dispatch((stream, scheme.clone())) {
+                        service_unavailable(stream, &scheme, &context);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => warn!("Error connecting to client! {}", e),
+            }
+        }
+        if idle {
+            thread::sleep(ACCEPT_POLL);
+        }
+    }
+
+    // Stop accepting before waiting on the workers
+    drop(listeners);
+
+    let drain = Duration::from_secs(config.drain_timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT));
+    let abandoned = pool.shutdown(drain);
+    if abandoned > 0 {
+        warn!(
+            "{} connection(s) still running after {:?}, abandoned",
+            abandoned, drain
+        );
+    }
+    if let Some(log) = &context.access_log {
+        log.flush();
+    }
+
+    Ok(())
+}
+
+// Turn a connection away because every worker is busy and the queue is
+// full. This runs on the listening thread so it must not block for long.
+fn service_unavailable(mut conn: TcpStream, scheme: &Scheme, context: &Context) {
+    if scheme.is_https() {
+        // Answering takes a handshake first, that can not wait
+        warn!("Every worker is busy, dropped an HTTPS connection");
+        return;
+    }
+
+    let config = &context.config;
+    let mut res = Response::error(StatusCode::ServiceUnavailable, config);
+    res.fields.insert(
+        field_to_string(&RequestField::RetryAfter),
+        config
+            .retry_after
+            .unwrap_or(DEFAULT_RETRY_AFTER)
+            .to_string(),
+    );
+
+    let _ = conn.set_write_timeout(Some(Duration::from_secs(1)));
+    let bytes = match res.respond(&mut conn, config) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Could not turn away a connection! err: {}", e);
+            0
+        }
+    };
+    let _ = conn.shutdown(Shutdown::Write);
+
+    if let Some(log) = &context.access_log {
+        log.log(&Entry {
+            addr: conn.peer_addr().ok(),
+            header: None,
+            status: res.status,
+            bytes,
+        });
+    }
+}
+
+// Answer a request with the configuration of its site, passing it through
+// the middleware on the way to the router and the response back through it
+// in reverse.
+fn answer(header: &mut Header, config: &Config, context: &Context) -> Response {
+    // Nothing can be said about a request that could not be parsed
+    let middleware = if header.is_valid() {
+        &context.middleware[..]
+    } else {
+        &[]
+    };
+    let mut ran = 0;
+    let mut early = None;
+
+    for m in middleware {
+        ran += 1;
+        if let Some(res) = m.before(header) {
+            early = Some(res.complete(config));
+            break;
+        }
+    }
+
+    let mut res = match early {
+        Some(res) => res,
+        None => answer_protected(header, config, context),
+    };
+    for m in middleware[..ran].iter().rev() {
+        m.after(header, &mut res);
+    }
+
+    res
+}
+
+// Answer a request past the middleware. A protected path is not looked at
+// before the user logs in, neither is the path a rewrite serves it from.
+fn answer_protected(header: &mut Header, config: &Config, context: &Context) -> Response {
+    if let Some(res) = context.auth.check(header, config) {
+        return res;
+    }
+
+    let requested = header.get_path().to_string();
+    let redirect = context.rules.apply(header, config);
+    let denied = match redirect {
+        None if header.get_path() != requested => context.auth.check(header, config),
+        _ => None,
+    };
+    match redirect.or(denied) {
+        Some(res) => res,
+        None => Response::new(header, config, &context.router),
+    }
+}
+
+// Set up a connection and serve it as its listener says.
+fn new_connection(mut conn: TcpStream, scheme: Scheme, context: &Context) {
+    // Gone already if the client hung up right away
+    let peer = match conn.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => return,
+    };
+    debug!("New connection from {}", peer);
+
+    let timeout = context
+        .config
+        .keep_alive_timeout
+        .unwrap_or(DEFAULT_KEEP_ALIVE_TIMEOUT);
+    if let Err(e) = conn.set_read_timeout(Some(Duration::from_secs(timeout.max(1)))) {
+        warn!("Could not set a read timeout! err: {}", e);
+    }
+    let timeout = context
+        .config
+        .write_timeout
+        .unwrap_or(DEFAULT_WRITE_TIMEOUT);
+    if let Err(e) = conn.set_write_timeout(Some(Duration::from_secs(timeout.max(1)))) {
+        warn!("Could not set a write timeout! err: {}", e);
+    }
+    // The header and a streamed body go out in separate writes
+    if let Err(e) = conn.set_nodelay(true) {
+        warn!("Could not disable Nagle's algorithm! err: {}", e);
+    }
+
+    match scheme {
+        Scheme::Http => serve(&mut conn, peer, None, context),
+        Scheme::Redirect(port) => serve(&mut conn, peer, Some(port), context),
+        #[cfg(feature = "tls")]
+        Scheme::Https(tls_config) => match tls::accept(&tls_config, conn) {
+            Ok(mut conn) => {
+                serve(&mut conn, peer, None, context);
+                tls::close(&mut conn);
+            }
+            Err(e) => warn!("{}", e.message),
+        },
+    }
+}
+
+// Serve the requests of a single connection. The connection stays open
+// for as long as the client asks for it, up to `keep_alive_max` requests,
+// and until the server shuts down. With `redirect` every request is sent
+// to HTTPS on that port instead of being answered.
+fn serve<S: Read + Sink>(conn: &mut S, peer: SocketAddr, redirect: Option<u16>, context: &Context) {
+    let config = &context.config;
+    let max_requests = config
+        .keep_alive_max
+        .unwrap_or(DEFAULT_KEEP_ALIVE_MAX)
+        .max(1);
+    let timeout = config
+        .keep_alive_timeout
+        .unwrap_or(DEFAULT_KEEP_ALIVE_TIMEOUT);
+
+    let mut buf = Vec::<u8>::new();
+    let mut served = 0;
+
+    loop {
+        let (mut res, keep_alive, header) = match request::read_request(conn, &mut buf, config) {
+            Ok(mut header) => {
+                if config.print_header_information.unwrap_or(false) {
+                    debug!("Request from {}\n{}", peer, header);
+                }
+                let mut res = match redirect {
+                    Some(port) => redirect_to_https(&header, port, config),
+                    None => {
+                        let site = context.vhosts.select(header.get_host());
+                        answer(&mut header, site.unwrap_or(config), context)
+                    }
+                };
+                res.frame(header.get_method());
+                // Nothing after a request that could not be parsed can be
+                // trusted to start the next one
+                let keep_alive = header.is_valid() && header.keep_alive();
+                (res, keep_alive, Some(header))
+            }
+            Err(ReadError::Invalid(e)) => {
+                info!(
+                    "Invalid request from {}: {} (line {}, column {})",
+                    peer, e.message, e.line, e.column
+                );
+                // The rest of the stream can not be trusted
+                (Response::error(e.status, config), false, None)
+            }
+            Err(ReadError::Closed) => return,
+            Err(ReadError::Io(e)) => {
+                info!(
+                    "An error occured while reading the stream! ip: {}, err: {}",
+                    peer, e
+                );
+                return;
+            }
+        };
+
+        served += 1;
+        let keep_alive =
+            keep_alive && served < max_requests && context.running.load(Ordering::SeqCst);
+        res.set_keep_alive(keep_alive, timeout, max_requests - served);
+
+        let sent = res
+            .respond(conn, config)
+            .and_then(|bytes| conn.flush().map(|_| bytes));
+
+        if let Some(log) = &context.access_log {
+            log.log(&Entry {
+                addr: Some(peer),
+                header: header.as_ref(),
+                status: res.status,
+                bytes: *sent.as_ref().unwrap_or(&0),
+            });
+        }
+
+        if let Err(e) = sent {
+            info!("Could not respond to {}! err: {}", peer, e);
+            return;
+        }
+        debug!("Responded to {} with {}", peer, res.status as u16);
+
+        if !keep_alive {
+            return;
+        }
+    }
+}
+
+// Send a request made over plain HTTP to the same resource over HTTPS on
+// `port`.
+fn redirect_to_https(header: &Header, port: u16, config: &Config) -> Response {
+    let mut res = Response::from_status(StatusCode::MovedPermanently);
+    res.set_field(
+        RequestField::Location,
+        &https_location(header, port, config),
+    );
+    res.complete(config)
+}
+
+// The URL of the resource `header` asks for on the HTTPS `port`, built from
+// its host, path and query rather than the request line, which may hold an
+// absolute URI.
+fn https_location(header: &Header, port: u16, config: &Config) -> String {
+    let host = header.get_host().unwrap_or(&config.host);
+    let port = match port {
+        443 => String::new(),
+        x => format!(":{}", x),
+    };
+    let path = match header.get_path() {
+        x if x.starts_with('/') => request::percent_encode(x),
+        _ => String::from("/"),
+    };
+    match header.get_query() {
+        "" => format!("https://{}{}{}", host, port, path),
+        query => format!("https://{}{}{}?{}", host, port, path, query),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(head: &str, port: u16) -> String {
+        let config = Config {
+            host: String::from("default.test"),
+            ..Config::default()
+        };
+        https_location(&Header::new(head.as_bytes()), port, &config)
+    }
+
+    #[test]
+    fn location_from_path_and_query() {
+        assert_eq!(
+            location(
+                "GET /a/../b%20c?x=1&y=%20 HTTP/1.1\r\nHost: Example.com:80\r\n\r\n",
+                443
+            ),
+            "https://example.com/b%20c?x=1&y=%20"
+        );
+        assert_eq!(
+            location("GET /a HTTP/1.1\r\nHost: example.com\r\n\r\n", 8443),
+            "https://example.com:8443/a"
+        );
+        assert_eq!(
+            location("GET / HTTP/1.0\r\n\r\n", 443),
+            "https://default.test/"
+        );
+    }
+
+    #[test]
+    fn location_from_absolute_uri() {
+        assert_eq!(
+            location(
+                "GET http://example.com/x/y?z HTTP/1.1\r\nHost: other.com\r\n\r\n",
+                443
+            ),
+            "https://example.com/x/y?z"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! HTTPS
+//!
+//! Connections are wrapped in TLS by rustls. The certificate is picked by
+//! the name the client asks for when it connects (SNI): one of the
+//! `[[certificate]]` tables if it lists the name, `cert_file` otherwise.
+//! Only built with the `tls` feature.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::body::Sink;
+use crate::configuration::Config;
+use crate::TinyHttpError;
+
+/// A connection to a client over TLS.
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+// Encrypted data can not be handed to `sendfile`
+impl Sink for TlsStream {}
+
+// Picks the certificate for the name in the client hello.
+#[derive(Debug)]
+struct Certificates {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let name = match hello.server_name() {
+            Some(name) => name.to_ascii_lowercase(),
+            None => return Some(Arc::clone(&self.default)),
+        };
+
+        let wildcard = name
+            .split_once('.')
+            .map(|(_, domain)| format!("*.{}", domain));
+        let key = self
+            .by_name
+            .get(&name)
+            .or_else(|| wildcard.and_then(|x| self.by_name.get(&x)))
+            .unwrap_or(&self.default);
+        Some(Arc::clone(key))
+    }
+}
+
+/// The TLS settings of the server, if `cert_file` is set.
+pub fn server_config(config: &Config) -> Result<Option<Arc<ServerConfig>>, TinyHttpError> {
+    let cert_file = match &config.cert_file {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let key_file = match &config.key_file {
+        Some(x) => x,
+        None => {
+            return Err(TinyHttpError {
+                message: "cert_file is set without a key_file".to_string(),
+            })
+        }
+    };
+
+    let provider = ring::default_provider();
+    let mut certificates = Certificates {
+        default: load(cert_file, key_file, &provider)?,
+        by_name: HashMap::new(),
+    };
+    for certificate in config.certificate.iter().flatten() {
+        let key = load(&certificate.cert_file, &certificate.key_file, &provider)?;
+        for name in &certificate.names {
+            certificates
+                .by_name
+                .insert(name.to_ascii_lowercase(), Arc::clone(&key));
+        }
+    }
+
+    let mut tls = match ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_safe_default_protocol_versions()
+    {
+        Ok(builder) => builder
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(certificates)),
+        Err(e) => {
+            return Err(TinyHttpError {
+                message: format!("Could not set up TLS! {}", e),
+            })
+        }
+    };
+    tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(Arc::new(tls)))
+}
+
+/// Start a TLS connection with a client. The handshake happens as the
+/// request is read.
+pub fn accept(tls: &Arc<ServerConfig>, conn: TcpStream) -> Result<TlsStream, TinyHttpError> {
+    match ServerConnection::new(Arc::clone(tls)) {
+        Ok(session) => Ok(StreamOwned::new(session, conn)),
+        Err(e) => Err(TinyHttpError {
+            message: format!("Could not start a TLS connection! {}", e),
+        }),
+    }
+}
+
+/// Tell the client the connection is about to be closed.
+pub fn close(conn: &mut TlsStream) {
+    conn.conn.send_close_notify();
+    // The client may be gone already
+    let _ = conn.flush();
+}
+
+// Read a certificate chain and its private key from PEM files.
+fn load(
+    cert_file: &str,
+    key_file: &str,
+    provider: &CryptoProvider,
+) -> Result<Arc<CertifiedKey>, TinyHttpError> {
+    let fail = |path: &str, e: String| TinyHttpError {
+        message: format!("Could not load {}! {}", path, e),
+    };
+    let open = |path: &str| match File::open(path) {
+        Ok(file) => Ok(BufReader::new(file)),
+        Err(e) => Err(fail(path, e.to_string())),
+    };
+
+    let certs = match rustls_pemfile::certs(&mut open(cert_file)?).collect::<Result<Vec<_>, _>>() {
+        Ok(certs) if !certs.is_empty() => certs,
+        Ok(_) => return Err(fail(cert_file, "No certificate found".to_string())),
+        Err(e) => return Err(fail(cert_file, e.to_string())),
+    };
+    let key = match rustls_pemfile::private_key(&mut open(key_file)?) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(fail(key_file, "No private key found".to_string())),
+        Err(e) => return Err(fail(key_file, e.to_string())),
+    };
+
+    match CertifiedKey::from_der(certs, key, provider) {
+        Ok(key) => Ok(Arc::new(key)),
+        Err(e) => Err(fail(key_file, e.to_string())),
+    }
+}
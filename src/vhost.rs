@@ -0,0 +1,185 @@
+//! Virtual Hosts
+//!
+//! Several sites served by one server, each from its own `doc_root`. The
+//! site is picked by the host a request is for. Every site gets a copy of
+//! the server's configuration with its own options put in, so serving a
+//! file works the same with or without virtual hosts.
+//!
+//! Greg Hairfield
+//! CS410P Rust Programming
+//! Spring 2021
+
+use crate::configuration::{Config, VirtualHost};
+use crate::TinyHttpError;
+
+/// The sites of a server.
+pub struct VirtualHosts {
+    sites: Vec<Site>,
+    /// Index of the site serving unknown names
+    default: Option<usize>,
+}
+
+struct Site {
+    names: Vec<String>,
+    config: Config,
+}
+
+impl VirtualHosts {
+    /// Set up the sites of the `[[vhost]]` tables of `config`.
+    pub fn new(config: &Config) -> Result<Self, TinyHttpError> {
+        let mut sites = Vec::new();
+        let mut default = None;
+
+        for (i, vhost) in config.vhost.iter().flatten().enumerate() {
+            if vhost.default.unwrap_or(false) {
+                if default.is_some() {
+                    return Err(TinyHttpError {
+                        message: format!(
+                            "{} is the second default virtual host",
+                            vhost.server_name
+                        ),
+                    });
+                }
+                default = Some(i);
+            }
+            sites.push(Site::new(vhost, config));
+        }
+
+        Ok(VirtualHosts { sites, default })
+    }
+
+    /// The configuration of the site serving `host`. `None` when the
+    /// server's own configuration does.
+    pub fn select(&self, host: Option<&str>) -> Option<&Config> {
+        let site = match host {
+            Some(host) => self
+                .sites
+                .iter()
+                .find(|x| x.names.iter().any(|name| name == host))
+                .or_else(|| self.longest_wildcard(host)),
+            None => None,
+        };
+
+        site.or_else(|| self.sites.get(self.default?))
+            .map(|x| &x.config)
+    }
+
+    // The site with the longest wildcard matching `host`, so
+    // `*.api.example.com` wins over `*.example.com` whichever is listed
+    // first. Of two as long the first listed wins.
+    fn longest_wildcard(&self, host: &str) -> Option<&Site> {
+        let mut best: Option<(&Site, usize)> = None;
+        for site in &self.sites {
+            for name in site.names.iter().filter(|x| wildcard_matches(x, host)) {
+                if best.is_none_or(|(_, len)| name.len() > len) {
+                    best = Some((site, name.len()));
+                }
+            }
+        }
+        best.map(|(site, _)| site)
+    }
+}
+
+impl Site {
+    fn new(vhost: &VirtualHost, server: &Config) -> Self {
+        let mut names = vec![vhost.server_name.to_ascii_lowercase()];
+        for alias in vhost.aliases.iter().flatten() {
+            names.push(alias.to_ascii_lowercase());
+        }
+
+        let mut config = server.clone();
+        config.doc_root = vhost.doc_root.clone();
+        if vhost.root_file.is_some() {
+            config.root_file = vhost.root_file.clone();
+        }
+        // The pages of the server are under its own `doc_root`
+        config.error_pages = vhost.error_pages.clone();
+        config.custom_404 = None;
+        config.vhost = None;
+
+        Site { names, config }
+    }
+}
+
+// Does `*.example.com` match `host`? Any subdomain does, at any depth.
+fn wildcard_matches(name: &str, host: &str) -> bool {
+    match name.strip_prefix('*') {
+        Some(domain) if domain.starts_with('.') => {
+            host.len() > domain.len() && host.ends_with(domain)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vhost(server_name: &str, aliases: &[&str]) -> VirtualHost {
+        VirtualHost {
+            server_name: server_name.to_string(),
+            aliases: Some(aliases.iter().map(|x| x.to_string()).collect()),
+            doc_root: format!("sites/{}", server_name),
+            root_file: None,
+            error_pages: None,
+            default: None,
+        }
+    }
+
+    // The `doc_root` of the site serving `host`.
+    fn selected<'a>(vhosts: &'a VirtualHosts, host: &str) -> Option<&'a str> {
+        vhosts.select(Some(host)).map(|x| x.doc_root.as_str())
+    }
+
+    #[test]
+    fn overlapping_wildcards() {
+        let orders = [
+            vec![vhost("*.example.com", &[]), vhost("*.api.example.com", &[])],
+            vec![vhost("*.api.example.com", &[]), vhost("*.example.com", &[])],
+        ];
+        for order in orders {
+            let config = Config {
+                vhost: Some(order),
+                ..Config::default()
+            };
+            let vhosts = VirtualHosts::new(&config).unwrap();
+            assert_eq!(
+                selected(&vhosts, "v1.api.example.com"),
+                Some("sites/*.api.example.com")
+            );
+            assert_eq!(
+                selected(&vhosts, "www.example.com"),
+                Some("sites/*.example.com")
+            );
+            assert_eq!(
+                selected(&vhosts, "api.example.com"),
+                Some("sites/*.example.com")
+            );
+            assert_eq!(selected(&vhosts, "example.com"), None);
+        }
+    }
+
+    #[test]
+    fn exact_names_and_aliases() {
+        let config = Config {
+            vhost: Some(vec![
+                vhost("*.example.com", &[]),
+                vhost("example.org", &["www.example.com", "*.a.b.example.com"]),
+            ]),
+            ..Config::default()
+        };
+        let vhosts = VirtualHosts::new(&config).unwrap();
+        assert_eq!(
+            selected(&vhosts, "www.example.com"),
+            Some("sites/example.org")
+        );
+        assert_eq!(
+            selected(&vhosts, "x.a.b.example.com"),
+            Some("sites/example.org")
+        );
+        assert_eq!(
+            selected(&vhosts, "x.b.example.com"),
+            Some("sites/*.example.com")
+        );
+    }
+}